@@ -0,0 +1,144 @@
+use super::RispExp;
+use super::RispErr;
+use super::RispValueString;
+
+// Rows end with \n or \r\n, quoted fields can hold commas, newlines
+// and doubled "" quotes.
+fn parse(text: &str) -> Result<Vec<Vec<String>>, RispErr> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut in_quote = false;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+	if in_quote {
+	    if c == '"' && chars.peek() == Some(&'"') {
+		field.push('"');
+		chars.next();
+	    } else if c == '"' {
+		in_quote = false;
+	    } else {
+		field.push(c);
+	    }
+	    continue;
+	}
+
+	match c {
+	    '"' => in_quote = true,
+	    ',' => row.push(std::mem::take(&mut field)),
+	    '\r' if chars.peek() == Some(&'\n') => (),
+	    '\n' => {
+		row.push(std::mem::take(&mut field));
+		rows.push(std::mem::take(&mut row));
+	    },
+	    _ => field.push(c)
+	}
+    }
+
+    if in_quote {
+	return Err(RispErr::Reason("unterminated quoted field".to_string()));
+    }
+    if !field.is_empty() || !row.is_empty() {
+	row.push(field);
+	rows.push(row);
+    }
+    Ok(rows)
+}
+
+fn quote_field(field: String) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+	format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+	field
+    }
+}
+
+pub fn parse_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    let text = match args.first() {
+		Some(RispExp::Str(s)) => s,
+		_ => return Err(RispErr::Reason("pass a csv string".to_string()))
+	    };
+	    let rows = parse(text)?
+		.into_iter()
+		.map(|row| RispExp::List(row.into_iter().map(RispExp::Str).collect()))
+		.collect();
+	    Ok(RispExp::List(rows))
+	}
+    )
+}
+
+pub fn write_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    let rows = match args.first() {
+		Some(RispExp::List(rows)) => rows,
+		_ => return Err(RispErr::Reason("pass a list of rows".to_string()))
+	    };
+	    let mut text = String::new();
+	    for row in rows {
+		let fields = match row {
+		    RispExp::List(fields) => fields,
+		    _ => return Err(RispErr::Reason("row is not a list".to_string()))
+		};
+		let line: Vec<String> = fields.iter().map(|f| quote_field(f.lisp_val())).collect();
+		text.push_str(&line.join(","));
+		text.push('\n');
+	    }
+	    Ok(RispExp::Str(text))
+	}
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+
+    fn rows(xs: &[&[&str]]) -> RispExp {
+	l(xs.iter().map(|row| l(row.iter().map(|f| s(f)).collect())).collect())
+    }
+
+    #[test]
+    fn parse_rows_and_fields() {
+	assert_eq!(
+	    call(parse_func(), &[s("a,b\n1,2\n")]),
+	    Ok(rows(&[&["a", "b"], &["1", "2"]]).to_string())
+	);
+	assert_eq!(call(parse_func(), &[s("a,b\r\n1,")]), Ok(rows(&[&["a", "b"], &["1", ""]]).to_string()));
+	assert_eq!(call(parse_func(), &[s("")]), Ok("()".to_string()));
+    }
+
+    #[test]
+    fn parse_quoted_fields() {
+	assert_eq!(
+	    call(parse_func(), &[s("\"a,b\",\"say \"\"hi\"\"\",\"two\nlines\"\n")]),
+	    Ok(rows(&[&["a,b", "say \"hi\"", "two\nlines"]]).to_string())
+	);
+	assert_eq!(call(parse_func(), &[s("\"open")]), Err("unterminated quoted field".to_string()));
+    }
+
+    #[test]
+    fn write_quotes_when_needed() {
+	let table = l(vec![
+	    l(vec![s("a,b"), s("say \"hi\""), n(1.0)]),
+	    l(vec![s("plain"), RispExp::Bool(true)])
+	]);
+	assert_eq!(
+	    call(write_func(), &[table]),
+	    Ok(s("\"a,b\",\"say \"\"hi\"\"\",1\nplain,true\n").to_string())
+	);
+	assert_eq!(call(write_func(), &[l(vec![s("x")])]), Err("row is not a list".to_string()));
+    }
+
+    #[test]
+    fn write_then_parse_round_trips() {
+	let table = rows(&[&["name", "note"], &["x", "a, \"b\"\nc"]]);
+	let written = call_exp(write_func(), std::slice::from_ref(&table)).unwrap();
+	assert_eq!(call(parse_func(), &[written]), Ok(table.to_string()));
+    }
+}
+
+// End
@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+use super::RispExp;
+use super::RispErr;
+use super::RispEnv;
+use super::RispLambda;
+use super::lists::Range;
+
+// Values hold Rc's, so they can't be moved to another thread as they
+// are. A Detached is a copy of a value and of every env its lambdas
+// can reach, sharing nothing with the original, which can be sent to
+// a thread and attached there to make values again.
+pub struct Detached {
+    value: Value,
+    env: Option<usize>,
+    envs: Vec<Env>,
+}
+
+enum Value {
+    Bool(bool),
+    Symbol(String),
+    Number(f64),
+    Str(String),
+    List(Vec<Value>),
+    Func(fn(&[RispExp]) -> Result<RispExp, RispErr>),
+    // env is an index into the envs of the Detached
+    Lambda { params: Box<Value>, body: Vec<Value>, env: usize },
+    Json(serde_json::Value),
+    Range(Range),
+}
+
+struct Env {
+    data: Vec<(String, Value)>,
+    consts: Vec<String>,
+    outer: Option<usize>,
+}
+
+// Envs are numbered by their data, the part clones of an env share,
+// so a lambda that sees its own env doesn't copy it forever
+struct Detacher {
+    envs: Vec<Env>,
+    seen: HashMap<*const (), usize>,
+}
+
+impl Detacher {
+    fn env(&mut self, env: &RispEnv) -> usize {
+	let key = Rc::as_ptr(&env.data) as *const ();
+	if let Some(i) = self.seen.get(&key) {
+	    return *i;
+	}
+	let i = self.envs.len();
+	self.envs.push(Env { data: Vec::new(), consts: Vec::new(), outer: None });
+	self.seen.insert(key, i);
+	let outer = env.outer.as_ref().map(|outer| self.env(outer));
+	let data = env.data.borrow().iter().map(|(k, v)| (k.clone(), self.value(v))).collect();
+	let consts = env.consts.borrow().iter().cloned().collect();
+	self.envs[i] = Env { data, consts, outer };
+	i
+    }
+
+    fn value(&mut self, exp: &RispExp) -> Value {
+	match exp {
+	    RispExp::Bool(b) => Value::Bool(*b),
+	    RispExp::Symbol(s) => Value::Symbol(s.clone()),
+	    RispExp::Number(n) => Value::Number(*n),
+	    RispExp::Str(s) => Value::Str(s.clone()),
+	    RispExp::List(list) => Value::List(list.iter().map(|x| self.value(x)).collect()),
+	    RispExp::Func(f) => Value::Func(*f),
+	    RispExp::Lambda(lambda) => Value::Lambda {
+		params: Box::new(self.value(&lambda.params_exp)),
+		body: lambda.body_exps.iter().map(|x| self.value(x)).collect(),
+		env: self.env(&lambda.env),
+	    },
+	    RispExp::Json(data) => Value::Json(data.as_ref().clone()),
+	    RispExp::Range(r) => Value::Range(*r),
+	}
+    }
+}
+
+// The env, if there is one, is the env the value will be evaluated in
+pub fn detach(exp: &RispExp, env: Option<&RispEnv>) -> Detached {
+    let mut detacher = Detacher { envs: Vec::new(), seen: HashMap::new() };
+    let value = detacher.value(exp);
+    let env = env.map(|env| detacher.env(env));
+    Detached { value, env, envs: detacher.envs }
+}
+
+struct Attacher {
+    envs: Vec<RispEnv>,
+}
+
+impl Attacher {
+    // Every env is made empty first, so a lambda can refer to any of
+    // them while the data is filled in
+    fn new(envs: &[Env]) -> Attacher {
+	let mut made: Vec<Option<RispEnv>> = vec![None; envs.len()];
+	for i in 0..envs.len() {
+	    make_env(envs, i, &mut made);
+	}
+	Attacher { envs: made.into_iter().map(|env| env.unwrap()).collect() }
+    }
+
+    fn fill(&self, envs: Vec<Env>) {
+	for (env, detached) in self.envs.iter().zip(envs) {
+	    for (k, v) in detached.data {
+		let value = self.value(v);
+		env.data.borrow_mut().insert(k, value);
+	    }
+	    env.consts.borrow_mut().extend(detached.consts);
+	}
+    }
+
+    fn value(&self, value: Value) -> RispExp {
+	match value {
+	    Value::Bool(b) => RispExp::Bool(b),
+	    Value::Symbol(s) => RispExp::Symbol(s),
+	    Value::Number(n) => RispExp::Number(n),
+	    Value::Str(s) => RispExp::Str(s),
+	    Value::List(list) => RispExp::List(list.into_iter().map(|x| self.value(x)).collect()),
+	    Value::Func(f) => RispExp::Func(f),
+	    Value::Lambda { params, body, env } => RispExp::Lambda(RispLambda {
+		params_exp: Rc::new(self.value(*params)),
+		body_exps: Rc::new(body.into_iter().map(|x| self.value(x)).collect()),
+		env: self.envs[env].clone(),
+	    }),
+	    Value::Json(data) => RispExp::Json(Rc::new(data)),
+	    Value::Range(r) => RispExp::Range(r),
+	}
+    }
+}
+
+fn make_env(envs: &[Env], i: usize, made: &mut Vec<Option<RispEnv>>) -> RispEnv {
+    if let Some(env) = &made[i] {
+	return env.clone();
+    }
+    let outer = envs[i].outer.map(|outer| make_env(envs, outer, made));
+    let env = RispEnv::new(HashMap::new(), outer.as_ref());
+    made[i] = Some(env.clone());
+    env
+}
+
+pub fn attach(detached: Detached) -> (RispExp, Option<RispEnv>) {
+    let attacher = Attacher::new(&detached.envs);
+    let value = attacher.value(detached.value);
+    let env = detached.env.map(|i| attacher.envs[i].clone());
+    attacher.fill(detached.envs);
+    (value, env)
+}
+
+// End
@@ -1,7 +1,13 @@
+use std::rc::Rc;
 use reqwest::blocking::get as httpget;
 use super::RispExp;
 use super::RispErr;
 use super::RispValueString;
+use super::lists::find_pair_ignore_case;
+
+// httpget returns (status url headers [body]), this is where the headers sit
+const HEADERS_INDEX: usize = 2;
+const BODY_INDEX: usize = 3;
 
 pub fn httpget_func() -> RispExp {
     RispExp::Func(|args: &[RispExp]| -> Result<RispExp, RispErr> {
@@ -26,7 +32,7 @@ pub fn httpget_func() -> RispExp {
 	for (name, value) in headers.iter() {
 	  let mut pair = Vec::new();
 	  pair.push(RispExp::Str(name.to_string()));
-	  pair.push(RispExp::Str(value.to_str().unwrap().to_string()));
+	  pair.push(RispExp::Str(String::from_utf8_lossy(value.as_bytes()).to_string()));
 	  header_list.push(RispExp::List(pair));
 	}
 
@@ -36,20 +42,104 @@ pub fn httpget_func() -> RispExp {
 	  RispExp::List(header_list)
 	];
 
-	let content_type = headers.get("content-type").unwrap().to_str().unwrap();
+	let content_type = headers.get("content-type")
+	  .and_then(|value| value.to_str().ok())
+	  .unwrap_or("")
+	  .to_string();
+	let text_content = match res.text_with_charset("utf-8") {
+	  Ok(text) => text,
+	  Err(e) => return Err(RispErr::Reason(e.to_string()))
+	};
 	if content_type.starts_with("application/json") {
-	  let text_content = res.text_with_charset("utf-8").unwrap();
 	  let json = match serde_json::from_str(&text_content) {
 	    Ok(data) => data,
 	    Err(e) => return Err(RispErr::Reason(e.to_string()))
 	  };
-	  let json = RispExp::Json(json);
+	  let json = RispExp::Json(Rc::new(json));
 	  response_list.push(json);
+	} else {
+	  // anything that isn't json is kept as text
+	  response_list.push(RispExp::Str(text_content));
 	}
 	Ok(RispExp::List(response_list))
       }
     )
 }
 
-// End
+pub fn header_func() -> RispExp {
+    RispExp::Func(|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	if args.len() != 2 {
+	    return Err(RispErr::Reason("pass a response and a header name".to_string()));
+	}
+	let headers = match &args[0] {
+	    RispExp::List(response) => match response.get(HEADERS_INDEX) {
+		Some(RispExp::List(headers)) => headers,
+		_ => return Err(RispErr::Reason("response has no header list".to_string()))
+	    },
+	    _ => return Err(RispErr::Reason("response is not a list".to_string()))
+	};
+	let name = match &args[1] {
+	    RispExp::Str(s) => s,
+	    _ => return Err(RispErr::Reason("header name is not a string".to_string()))
+	};
+
+	match find_pair_ignore_case(name, headers) {
+	    Some(RispExp::List(pair)) if pair.len() > 1 => Ok(pair[1].clone()),
+	    _ => Ok(RispExp::List(vec![]))
+	}
+      }
+    )
+}
 
+// The lines of a text response's body, or of a body string, a body
+// with no text in it has no lines
+pub fn response_lines_func() -> RispExp {
+    RispExp::Func(|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	let body = match args {
+	    [RispExp::Str(body)] => body,
+	    [RispExp::List(response)] => match response.get(BODY_INDEX) {
+		Some(RispExp::Str(body)) => body,
+		Some(_) => return Err(RispErr::Reason("response body is not text".to_string())),
+		None => return Ok(RispExp::List(vec![]))
+	    },
+	    _ => return Err(RispErr::Reason("pass a response or a body string".to_string()))
+	};
+	Ok(RispExp::List(body.lines().map(|line| RispExp::Str(line.to_string())).collect()))
+      }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+
+    fn response(body: RispExp) -> RispExp {
+	l(vec![
+	    n(200.0),
+	    s("http://example.com/"),
+	    l(vec![l(vec![s("Content-Type"), s("text/plain")])]),
+	    body
+	])
+    }
+
+    #[test]
+    fn header_ignores_case() {
+	let res = response(s(""));
+	assert_eq!(call(header_func(), &[res.clone(), s("content-type")]), Ok("\"text/plain\"".to_string()));
+	assert_eq!(call(header_func(), &[res.clone(), s("CONTENT-TYPE")]), Ok("\"text/plain\"".to_string()));
+	assert_eq!(call(header_func(), &[res, s("accept")]), Ok("()".to_string()));
+	assert_eq!(call(header_func(), &[s("x"), s("accept")]), Err("response is not a list".to_string()));
+    }
+
+    #[test]
+    fn response_lines_of_the_body() {
+	assert_eq!(call(response_lines_func(), &[response(s("a\nb\n"))]), Ok("(\"a\",\"b\")".to_string()));
+	assert_eq!(call(response_lines_func(), &[s("one\r\ntwo")]), Ok("(\"one\",\"two\")".to_string()));
+	assert_eq!(call(response_lines_func(), &[response(s(""))]), Ok("()".to_string()));
+	let json = RispExp::Json(Rc::new(serde_json::json!({})));
+	assert_eq!(call(response_lines_func(), &[response(json)]), Err("response body is not text".to_string()));
+    }
+}
+
+// End
@@ -1,6 +1,9 @@
+use std::fs;
+use std::rc::Rc;
 use serde_json;
 use super::RispExp;
 use super::RispErr;
+use super::lists::alist_position;
 
 pub fn display(data: &serde_json::Value) -> String {
     format!("{}", serde_json::to_string_pretty(data).unwrap())
@@ -13,17 +16,676 @@ pub fn get_func() -> RispExp {
 		return Err(RispErr::Reason("pass a json object and an index".to_string()));
 	    }
 
-	    let index = match &args[1] {
-		RispExp::Str(s) => s.to_string(),
-		RispExp::Number(n) => n.to_string(),
+	    let data = match &args[0] {
+		RispExp::Json(data) => data,
+		_ => return Err(RispErr::Reason("not a json object".to_string()))
+	    };
+	    let value = match &args[1] {
+		RispExp::Str(s) => &data[s.as_str()],
+		RispExp::Number(n) => match as_index(*n) {
+		    Some(i) => &data[i],
+		    None => return Err(RispErr::Reason(format!("index must be a non-negative integer: {}", n)))
+		},
 		_ => return Err(RispErr::Reason("index must be string or number".to_string()))
 	    };
+	    Ok(RispExp::Json(Rc::new(value.clone())))
+	}
+    )
+}
+
+// One step of get-in, None when there's nothing at that key
+fn get_step(data: &RispExp, step: &RispExp) -> Option<RispExp> {
+    match (data, step) {
+	(RispExp::Json(data), RispExp::Str(key)) => data.as_object()?.get(key)
+	    .map(|v| RispExp::Json(Rc::new(v.clone()))),
+	(RispExp::Json(data), RispExp::Number(i)) => data.as_array()?.get(as_index(*i)?)
+	    .map(|v| RispExp::Json(Rc::new(v.clone()))),
+	(RispExp::List(list), RispExp::Number(i)) if as_index(*i).is_some() => list.get(as_index(*i)?).cloned(),
+	(RispExp::List(alist), key) => match &alist[alist_position(key, alist)?] {
+	    RispExp::List(kv) => kv.get(1).cloned(),
+	    _ => None
+	},
+	_ => None
+    }
+}
+
+// Walks a path of keys and indexes through json, lists and assoc
+// lists, a missing step gives nil
+pub fn get_in_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    let (data, path) = match args {
+		[data, RispExp::List(path)] => (data, path),
+		_ => return Err(RispErr::Reason("pass a value and a path list".to_string()))
+	    };
+	    let mut current = data.clone();
+	    for step in path {
+		current = match get_step(&current, step) {
+		    Some(next) => next,
+		    None => return Ok(RispExp::List(vec![]))
+		};
+	    }
+	    Ok(current)
+	}
+    )
+}
+
+// get-in's single step, with a default for anything missing
+pub fn get_or_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    match args {
+		[data, key, default] => Ok(get_step(data, key).unwrap_or_else(|| default.clone())),
+		_ => Err(RispErr::Reason("pass a collection, a key and a default".to_string()))
+	    }
+	}
+    )
+}
+
+enum PathStep {
+    Key(String),
+    Index(usize),
+    Wildcard,
+}
+
+// The JSONPath subset: $ followed by .key, .*, [n] and [*]
+fn parse_path(path: &str) -> Result<Vec<PathStep>, RispErr> {
+    let unsupported = || RispErr::Reason(format!("unsupported json path: {}", path));
+    let rest = path.strip_prefix('$').ok_or_else(unsupported)?;
+    let mut steps = Vec::new();
+    let mut chars = rest.chars().peekable();
+    while let Some(c) = chars.next() {
+	match c {
+	    '.' => {
+		let mut key = String::new();
+		while let Some(&c) = chars.peek() {
+		    if c == '.' || c == '[' {
+			break;
+		    }
+		    key.push(c);
+		    chars.next();
+		}
+		match key.as_str() {
+		    "" => return Err(unsupported()),
+		    "*" => steps.push(PathStep::Wildcard),
+		    _ => steps.push(PathStep::Key(key))
+		}
+	    },
+	    '[' => {
+		let mut index = String::new();
+		loop {
+		    match chars.next() {
+			Some(']') => break,
+			Some(c) => index.push(c),
+			None => return Err(unsupported())
+		    }
+		}
+		match index.as_str() {
+		    "*" => steps.push(PathStep::Wildcard),
+		    _ => steps.push(PathStep::Index(index.parse().map_err(|_| unsupported())?))
+		}
+	    },
+	    _ => return Err(unsupported())
+	}
+    }
+    Ok(steps)
+}
+
+fn query<'a>(data: &'a serde_json::Value, steps: &[PathStep], found: &mut Vec<&'a serde_json::Value>) {
+    let (step, rest) = match steps.split_first() {
+	Some(split) => split,
+	None => return found.push(data)
+    };
+    match step {
+	PathStep::Key(key) => if let Some(v) = data.get(key) { query(v, rest, found) },
+	PathStep::Index(i) => if let Some(v) = data.get(i) { query(v, rest, found) },
+	PathStep::Wildcard => match data {
+	    serde_json::Value::Array(items) => items.iter().for_each(|v| query(v, rest, found)),
+	    serde_json::Value::Object(items) => items.values().for_each(|v| query(v, rest, found)),
+	    _ => ()
+	}
+    }
+}
+
+pub fn path_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    if args.len() != 2 {
+		return Err(RispErr::Reason("pass a json object and a path".to_string()));
+	    }
+
+	    let path = match &args[1] {
+		RispExp::Str(s) => parse_path(s)?,
+		_ => return Err(RispErr::Reason("path must be a string".to_string()))
+	    };
 	    match &args[0] {
-		RispExp::Json(data) => Ok(RispExp::Json(data[index].clone())),
+		RispExp::Json(data) => {
+		    let mut found = Vec::new();
+		    query(data, &path, &mut found);
+		    Ok(RispExp::List(found.into_iter().map(|v| RispExp::Json(Rc::new(v.clone()))).collect()))
+		},
 		_ => Err(RispErr::Reason("not a json object".to_string()))
 	    }
 	}
     )
 }
 
+// Native risp values as json, lists become arrays
+pub fn from_risp(exp: &RispExp) -> Result<serde_json::Value, RispErr> {
+    match exp {
+	RispExp::Bool(b) => Ok(serde_json::Value::Bool(*b)),
+	RispExp::Number(n) if n.fract() == 0.0 && n.abs() < i64::MAX as f64 => Ok(serde_json::Value::from(*n as i64)),
+	RispExp::Number(n) => serde_json::Number::from_f64(*n)
+	    .map(serde_json::Value::Number)
+	    .ok_or(RispErr::Reason(format!("{} is not a json number", n))),
+	RispExp::Str(s) => Ok(serde_json::Value::String(s.clone())),
+	RispExp::Symbol(s) => Ok(serde_json::Value::String(s.clone())),
+	RispExp::List(list) => Ok(serde_json::Value::Array(
+	    list.iter().map(from_risp).collect::<Result<Vec<_>, _>>()?
+	)),
+	RispExp::Json(data) => Ok(data.as_ref().clone()),
+	RispExp::Range(range) => Ok(serde_json::Value::Array(
+	    range.iter().map(|n| from_risp(&RispExp::Number(n))).collect::<Result<Vec<_>, _>>()?
+	)),
+	_ => Err(RispErr::Reason("functions can't be converted to json".to_string()))
+    }
+}
+
+// A number used as an array index, only whole numbers that aren't
+// negative are
+fn as_index(n: f64) -> Option<usize> {
+    if n >= 0.0 && n.fract() == 0.0 {
+	Some(n as usize)
+    } else {
+	None
+    }
+}
+
+fn set_in(data: &mut serde_json::Value, path: &[RispExp], value: serde_json::Value) -> Result<(), RispErr> {
+    let (step, rest) = match path.split_first() {
+	Some(split) => split,
+	None => {
+	    *data = value;
+	    return Ok(());
+	}
+    };
+    let child = match step {
+	RispExp::Str(key) => {
+	    if data.is_null() {
+		*data = serde_json::Value::Object(serde_json::Map::new());
+	    }
+	    match data {
+		serde_json::Value::Object(map) => map.entry(key.clone()).or_insert(serde_json::Value::Null),
+		_ => return Err(RispErr::Reason(format!("cannot set key {} in a non object", key)))
+	    }
+	},
+	RispExp::Number(n) => match data {
+	    serde_json::Value::Array(items) => match as_index(*n) {
+		Some(i) if i < items.len() => &mut items[i],
+		Some(_) => return Err(RispErr::Reason(format!("index {} out of range", n))),
+		None => return Err(RispErr::Reason(format!("index must be a non-negative integer: {}", n)))
+	    },
+	    _ => return Err(RispErr::Reason(format!("cannot set index {} in a non array", n)))
+	},
+	_ => return Err(RispErr::Reason("path must be strings or numbers".to_string()))
+    };
+    set_in(child, rest, value)
+}
+
+// Returns a new json value, missing objects on the path are created
+pub fn set_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    if args.len() < 3 {
+		return Err(RispErr::Reason("pass a json object, a path and a value".to_string()));
+	    }
+
+	    let mut data = match &args[0] {
+		RispExp::Json(data) => data.as_ref().clone(),
+		_ => return Err(RispErr::Reason("not a json object".to_string()))
+	    };
+	    let value = from_risp(&args[args.len() - 1])?;
+	    set_in(&mut data, &args[1..args.len() - 1], value)?;
+	    Ok(RispExp::Json(Rc::new(data)))
+	}
+    )
+}
+
+// Elements of an array or keys of an object, scalars have no count
+pub fn count(data: &serde_json::Value) -> Result<usize, RispErr> {
+    match data {
+	serde_json::Value::Array(items) => Ok(items.len()),
+	serde_json::Value::Object(items) => Ok(items.len()),
+	_ => Err(RispErr::Reason("json scalars have no count".to_string()))
+    }
+}
+
+pub fn count_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    match args.first() {
+		Some(RispExp::Json(data)) => Ok(RispExp::Number(count(data)? as f64)),
+		_ => Err(RispErr::Reason("not a json object".to_string()))
+	    }
+	}
+    )
+}
+
+// Works on json objects and arrays and on assoc lists
+pub fn contains_key_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    if args.len() != 2 {
+		return Err(RispErr::Reason("pass a collection and a key".to_string()));
+	    }
+
+	    let found = match (&args[0], &args[1]) {
+		(RispExp::Json(data), RispExp::Str(key)) if data.is_object() => data.get(key).is_some(),
+		(RispExp::Json(data), RispExp::Number(i)) if data.is_array() => {
+		    as_index(*i).is_some_and(|i| data.get(i).is_some())
+		},
+		(RispExp::Json(_), _) => return Err(RispErr::Reason("not a json object or array".to_string())),
+		(RispExp::List(alist), key) => alist_position(key, alist).is_some(),
+		_ => return Err(RispErr::Reason("not a json object or assoc list".to_string()))
+	    };
+	    Ok(RispExp::Bool(found))
+	}
+    )
+}
+
+// An object's (key value) pairs in insertion order, an assoc list
+// already is its pairs
+pub fn pairs_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    match args.first() {
+		Some(RispExp::Json(data)) => match data.as_object() {
+		    Some(map) => Ok(RispExp::List(map.iter().map(|(k, v)| RispExp::List(vec![
+			RispExp::Str(k.clone()),
+			RispExp::Json(Rc::new(v.clone()))
+		    ])).collect())),
+		    None => Err(RispErr::Reason("not a json object".to_string()))
+		},
+		Some(RispExp::List(alist)) => Ok(RispExp::List(alist.clone())),
+		_ => Err(RispErr::Reason("pass a json object or assoc list".to_string()))
+	    }
+	}
+    )
+}
+
+// A json object from (key value) pairs, like the http header list,
+// where a key that comes again replaces the earlier value
+pub fn map_from_pairs_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    let pairs = match args {
+		[RispExp::List(pairs)] => pairs,
+		_ => return Err(RispErr::Reason("pass a list of pairs".to_string()))
+	    };
+	    let mut map = serde_json::Map::new();
+	    for pair in pairs {
+		match pair {
+		    RispExp::List(kv) if kv.len() == 2 => match &kv[0] {
+			RispExp::Str(k) => { map.insert(k.clone(), from_risp(&kv[1])?); },
+			k => return Err(RispErr::Reason(format!("map keys must be strings: {}", k)))
+		    },
+		    _ => return Err(RispErr::Reason(format!("not a pair: {}", pair)))
+		}
+	    }
+	    Ok(RispExp::Json(Rc::new(serde_json::Value::Object(map))))
+	}
+    )
+}
+
+// Objects at the same key are merged all the way down, anything else
+// in b, arrays included, replaces what's in a
+fn deep_merge_json(a: &mut serde_json::Value, b: &serde_json::Value) {
+    match (a, b) {
+	(serde_json::Value::Object(a), serde_json::Value::Object(b)) => {
+	    for (k, v) in b {
+		match a.get_mut(k) {
+		    Some(existing) => deep_merge_json(existing, v),
+		    None => { a.insert(k.clone(), v.clone()); }
+		}
+	    }
+	},
+	(a, b) => *a = b.clone()
+    }
+}
+
+// The same for assoc lists, a value is merged when it's an assoc list
+// in both
+fn deep_merge_alist(a: &[RispExp], b: &[RispExp]) -> Result<Vec<RispExp>, RispErr> {
+    let mut result = a.to_vec();
+    for pair in b {
+	let (key, value) = match pair {
+	    RispExp::List(kv) if kv.len() == 2 => (&kv[0], &kv[1]),
+	    _ => return Err(RispErr::Reason(format!("not an assoc list: {}", pair)))
+	};
+	match alist_position(key, &result) {
+	    Some(i) => {
+		let merged = match (&result[i], value) {
+		    (RispExp::List(kv), RispExp::List(new)) if is_alist(new) => match kv.get(1) {
+			Some(RispExp::List(old)) if is_alist(old) => RispExp::List(deep_merge_alist(old, new)?),
+			_ => value.clone()
+		    },
+		    _ => value.clone()
+		};
+		result[i] = RispExp::List(vec![key.clone(), merged]);
+	    },
+	    None => result.push(pair.clone())
+	}
+    }
+    Ok(result)
+}
+
+fn is_alist(list: &[RispExp]) -> bool {
+    !list.is_empty() && list.iter().all(|x| matches!(x, RispExp::List(kv) if kv.len() == 2))
+}
+
+pub fn deep_merge_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    match args {
+		[RispExp::Json(a), RispExp::Json(b)] if a.is_object() && b.is_object() => {
+		    let mut merged = a.as_ref().clone();
+		    deep_merge_json(&mut merged, b);
+		    Ok(RispExp::Json(Rc::new(merged)))
+		},
+		[RispExp::List(a), RispExp::List(b)] => Ok(RispExp::List(deep_merge_alist(a, b)?)),
+		[_, _] => Err(RispErr::Reason("pass two json objects or two assoc lists".to_string())),
+		_ => Err(RispErr::Reason("pass two maps to merge".to_string()))
+	    }
+	}
+    )
+}
+
+pub fn slurp_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    let path = match args {
+		[RispExp::Str(path)] => path,
+		_ => return Err(RispErr::Reason("pass a file name".to_string()))
+	    };
+	    let text = fs::read_to_string(path)
+		.map_err(|e| RispErr::Reason(format!("{}: {}", path, e)))?;
+	    let data = serde_json::from_str(&text)
+		.map_err(|e| RispErr::Reason(format!("{}: {}", path, e)))?;
+	    Ok(RispExp::Json(Rc::new(data)))
+	}
+    )
+}
+
+// Written pretty so the file is easy to edit by hand
+pub fn spit_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    let (path, value) = match args {
+		[RispExp::Str(path), value] => (path, value),
+		_ => return Err(RispErr::Reason("pass a file name and a value".to_string()))
+	    };
+	    let text = display(&from_risp(value)?);
+	    fs::write(path, text + "\n")
+		.map_err(|e| RispErr::Reason(format!("{}: {}", path, e)))?;
+	    Ok(RispExp::List(vec![]))
+	}
+    )
+}
+
+pub fn stringify_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    let exp = args.first().ok_or(RispErr::Reason("pass a value".to_string()))?;
+	    Ok(RispExp::Str(from_risp(exp)?.to_string()))
+	}
+    )
+}
+
+fn is_pairs(list: &[RispExp]) -> bool {
+    !list.is_empty() && list.iter().all(|x| match x {
+	RispExp::List(kv) => kv.len() == 2 && matches!(kv[0], RispExp::Str(_)),
+	_ => false
+    })
+}
+
+// Like from_risp but a list of (string value) pairs becomes an object
+fn infer_json(exp: &RispExp) -> Result<serde_json::Value, RispErr> {
+    match exp {
+	RispExp::List(list) if is_pairs(list) => {
+	    let mut map = serde_json::Map::new();
+	    for pair in list {
+		if let RispExp::List(kv) = pair {
+		    if let RispExp::Str(k) = &kv[0] {
+			map.insert(k.clone(), infer_json(&kv[1])?);
+		    }
+		}
+	    }
+	    Ok(serde_json::Value::Object(map))
+	},
+	RispExp::List(list) => Ok(serde_json::Value::Array(
+	    list.iter().map(infer_json).collect::<Result<Vec<_>, _>>()?
+	)),
+	_ => from_risp(exp)
+    }
+}
+
+pub fn list_to_json_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    let exp = args.first().ok_or(RispErr::Reason("pass a list".to_string()))?;
+	    Ok(RispExp::Json(Rc::new(infer_json(exp)?)))
+	}
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+    use serde_json::json;
+
+    fn call_err(f: RispExp, args: &[RispExp]) -> String {
+	call(f, args).unwrap_err()
+    }
+
+    fn j(data: serde_json::Value) -> RispExp {
+	RispExp::Json(Rc::new(data))
+    }
+
+    fn pair(k: &str, v: RispExp) -> RispExp {
+	l(vec![s(k), v])
+    }
+
+    // The json a builtin returned, anything else fails the test
+    fn call_json(f: RispExp, args: &[RispExp]) -> serde_json::Value {
+	match call_exp(f, args) {
+	    Ok(RispExp::Json(data)) => data.as_ref().clone(),
+	    Ok(other) => panic!("not json: {}", other),
+	    Err(msg) => panic!("{}", msg)
+	}
+    }
+
+    #[test]
+    fn path_queries() {
+	let data = j(json!({"items": [{"id": 1}, {"id": 2}], "name": "x"}));
+	let found = |path: &str| match call_exp(path_func(), &[data.clone(), s(path)]) {
+	    Ok(RispExp::List(found)) => found.iter().map(|x| match x {
+		RispExp::Json(v) => v.as_ref().clone(),
+		_ => panic!("not json")
+	    }).collect::<Vec<_>>(),
+	    _ => panic!("not a list")
+	};
+	assert_eq!(found("$.name"), vec![json!("x")]);
+	assert_eq!(found("$.items[1].id"), vec![json!(2)]);
+	assert_eq!(found("$.items[*].id"), vec![json!(1), json!(2)]);
+	assert_eq!(found("$.missing"), Vec::<serde_json::Value>::new());
+	assert_eq!(call_err(path_func(), &[data.clone(), s("items")]), "unsupported json path: items");
+	assert_eq!(call_err(path_func(), &[data, s("$.items[")]), "unsupported json path: $.items[");
+    }
+
+    #[test]
+    fn set_creates_missing_objects() {
+	let data = j(json!({}));
+	assert_eq!(call_json(set_func(), &[data, s("a"), s("b"), n(1.0)]), json!({"a": {"b": 1}}));
+	let data = j(json!({"a": [1, 2]}));
+	assert_eq!(call_json(set_func(), &[data, s("a"), n(1.0), s("x")]), json!({"a": [1, "x"]}));
+    }
+
+    #[test]
+    fn set_leaves_the_original_alone() {
+	let original = Rc::new(json!({"a": 1}));
+	call_json(set_func(), &[RispExp::Json(original.clone()), s("a"), n(2.0)]);
+	assert_eq!(*original, json!({"a": 1}));
+    }
+
+    #[test]
+    fn set_checks_indexes() {
+	let data = j(json!([1, 2]));
+	assert_eq!(call_err(set_func(), &[data.clone(), n(5.0), n(0.0)]), "index 5 out of range");
+	assert_eq!(call_err(set_func(), &[data.clone(), n(-1.0), n(0.0)]), "index must be a non-negative integer: -1");
+	assert_eq!(call_err(set_func(), &[data.clone(), n(0.5), n(0.0)]), "index must be a non-negative integer: 0.5");
+	assert_eq!(call_err(set_func(), &[data, s("a"), n(0.0)]), "cannot set key a in a non object");
+    }
+
+    #[test]
+    fn count_arrays_and_objects() {
+	assert_eq!(call(count_func(), &[j(json!([1, 2, 3]))]).unwrap(), "3");
+	assert_eq!(call(count_func(), &[j(json!({"a": 1}))]).unwrap(), "1");
+	assert_eq!(call_err(count_func(), &[j(json!(1))]), "json scalars have no count");
+    }
+
+    #[test]
+    fn contains_key_on_json_and_alists() {
+	let contains = |coll: RispExp, key: RispExp| call(contains_key_func(), &[coll, key]).unwrap();
+	assert_eq!(contains(j(json!({"a": null})), s("a")), "true");
+	assert_eq!(contains(j(json!({"a": 1})), s("b")), "false");
+	assert_eq!(contains(j(json!([1, 2])), n(1.0)), "true");
+	assert_eq!(contains(j(json!([1, 2])), n(2.0)), "false");
+	assert_eq!(contains(j(json!([1, 2])), n(0.5)), "false");
+	assert_eq!(contains(j(json!([1, 2])), n(-1.0)), "false");
+	assert_eq!(contains(l(vec![pair("a", n(1.0))]), s("a")), "true");
+	assert_eq!(contains(l(vec![pair("a", n(1.0))]), s("b")), "false");
+	assert_eq!(call_err(contains_key_func(), &[j(json!(1)), s("a")]), "not a json object or array");
+    }
+
+    #[test]
+    fn from_risp_converts_natives() {
+	let exp = l(vec![n(1.0), n(1.5), s("a"), RispExp::Bool(true)]);
+	assert_eq!(from_risp(&exp).ok().unwrap(), json!([1, 1.5, "a", true]));
+	assert!(from_risp(&n(f64::NAN)).is_err());
+    }
+
+    #[test]
+    fn stringify_and_list_to_json() {
+	let value = l(vec![pair("a", n(1.0)), pair("b", l(vec![n(2.0)]))]);
+	assert_eq!(call(stringify_func(), std::slice::from_ref(&value)).unwrap(), "\"[[\"a\",1],[\"b\",[2]]]\"");
+	assert_eq!(call_json(list_to_json_func(), &[value]), json!({"a": 1, "b": [2]}));
+	assert_eq!(call_json(list_to_json_func(), &[l(vec![n(1.0), n(2.0)])]), json!([1, 2]));
+    }
+
+    #[test]
+    fn pairs_of_a_json_object() {
+	let data = j(json!({"b": 1, "a": [true]}));
+	assert_eq!(call(pairs_func(), &[data]), Ok(l(vec![
+	    pair("b", j(json!(1))),
+	    pair("a", j(json!([true])))
+	]).to_string()));
+	assert_eq!(call_err(pairs_func(), &[j(json!([1]))]), "not a json object");
+    }
+
+    #[test]
+    fn get_in_walks_json_lists_and_alists() {
+	let data = j(json!({"a": {"b": [1, 2, 3]}}));
+	let path = l(vec![s("a"), s("b"), n(2.0)]);
+	assert_eq!(call_json(get_in_func(), &[data.clone(), path]), json!(3));
+	let missing = l(vec![s("a"), s("x"), n(0.0)]);
+	assert_eq!(call(get_in_func(), &[data, missing]).unwrap(), "()");
+	let alist = l(vec![pair("k", l(vec![n(7.0), n(8.0)]))]);
+	let path = l(vec![s("k"), n(1.0)]);
+	assert_eq!(call(get_in_func(), &[alist, path]).unwrap(), "8");
+    }
+
+    #[test]
+    fn get_in_needs_whole_indexes() {
+	let data = j(json!([1, 2]));
+	assert_eq!(call(get_in_func(), &[data.clone(), l(vec![n(0.5)])]).unwrap(), "()");
+	assert_eq!(call(get_in_func(), &[data, l(vec![n(-1.0)])]).unwrap(), "()");
+    }
+
+    #[test]
+    fn get_by_key_and_index() {
+	let data = j(json!({"a": [10, 20]}));
+	assert_eq!(call_json(get_func(), &[data.clone(), s("a")]), json!([10, 20]));
+	assert_eq!(call_json(get_func(), &[j(json!([10, 20])), n(1.0)]), json!(20));
+	assert_eq!(call_json(get_func(), &[data.clone(), s("missing")]), json!(null));
+	assert_eq!(call_err(get_func(), &[j(json!([10])), n(-1.0)]), "index must be a non-negative integer: -1");
+	assert_eq!(call_err(get_func(), &[j(json!([10])), n(0.5)]), "index must be a non-negative integer: 0.5");
+    }
+
+    #[test]
+    fn get_or_defaults() {
+	let data = j(json!({"a": 1}));
+	assert_eq!(call_json(get_or_func(), &[data.clone(), s("a"), n(0.0)]), json!(1));
+	assert_eq!(call(get_or_func(), &[data, s("b"), n(0.0)]).unwrap(), "0");
+    }
+
+    #[test]
+    fn deep_merge_json_objects() {
+	let a = j(json!({"a": {"x": 1, "y": 2}, "list": [1, 2]}));
+	let b = j(json!({"a": {"y": 3}, "list": [3]}));
+	assert_eq!(call_json(deep_merge_func(), &[a, b]), json!({"a": {"x": 1, "y": 3}, "list": [3]}));
+    }
+
+    #[test]
+    fn deep_merge_alists() {
+	let a = l(vec![pair("a", l(vec![pair("x", n(1.0)), pair("y", n(2.0))]))]);
+	let b = l(vec![pair("a", l(vec![pair("y", n(3.0))])), pair("b", n(4.0))]);
+	let expected = l(vec![
+	    pair("a", l(vec![pair("x", n(1.0)), pair("y", n(3.0))])),
+	    pair("b", n(4.0))
+	]);
+	assert_eq!(call(deep_merge_func(), &[a, b]).unwrap(), expected.to_string());
+	assert_eq!(call_err(deep_merge_func(), &[j(json!({})), l(vec![])]), "pass two json objects or two assoc lists");
+    }
+
+    #[test]
+    fn from_risp_lists_a_range() {
+	let range = call_exp(crate::lists::range_func(), &numbers(&[3.0])).unwrap();
+	assert_eq!(from_risp(&range).ok().unwrap(), json!([0, 1, 2]));
+    }
+
+    #[test]
+    fn spit_then_slurp_round_trips() {
+	let path = std::env::temp_dir().join(format!("risp-spit-{}.json", std::process::id()));
+	let path = s(path.to_str().unwrap());
+	let value = l(vec![n(1.0), s("two"), RispExp::Bool(false)]);
+	assert_eq!(call(spit_func(), &[path.clone(), value]).unwrap(), "()");
+	let slurped = call_json(slurp_func(), std::slice::from_ref(&path));
+	if let RispExp::Str(p) = &path {
+	    fs::remove_file(p).unwrap();
+	}
+	assert_eq!(slurped, json!([1, "two", false]));
+    }
+
+    #[test]
+    fn slurp_names_the_missing_file() {
+	assert!(call_err(slurp_func(), &[s("/no/such/file.json")]).starts_with("/no/such/file.json: "));
+    }
+
+    #[test]
+    fn pairs_round_trip_through_map_from_pairs() {
+	let pairs = call_exp(pairs_func(), &[j(json!({"b": 1, "a": [true]}))]).unwrap();
+	assert_eq!(call_json(map_from_pairs_func(), &[pairs]), json!({"b": 1, "a": [true]}));
+    }
+
+    #[test]
+    fn map_from_pairs_keeps_the_last_value() {
+	let pairs = l(vec![pair("a", n(1.0)), pair("a", n(2.0))]);
+	assert_eq!(call_json(map_from_pairs_func(), &[pairs]), json!({"a": 2}));
+	let bad_key = l(vec![l(vec![n(1.0), n(2.0)])]);
+	assert_eq!(call_err(map_from_pairs_func(), &[bad_key]), "map keys must be strings: 1");
+	assert_eq!(call_err(map_from_pairs_func(), &[l(vec![n(1.0)])]), "not a pair: 1");
+    }
+}
+
 // End
@@ -1,5 +1,8 @@
+use std::cmp::Ordering;
 use super::RispExp;
 use super::RispErr;
+use super::apply_function;
+use super::jsontypes;
 
 pub fn list() -> RispExp {
     RispExp::Func(
@@ -22,7 +25,7 @@ pub fn car() -> RispExp {
 
 	    let l = match &args[0] {
 		RispExp::List(list) => list,
-		_ => return Err(RispErr::Reason("arg is not a list".to_string()))
+		_ => return Err(RispErr::Reason(format!("arg is not a list: {}", args[0])))
 	    };
 	    
 	    if l.len() < 1 {
@@ -43,7 +46,7 @@ pub fn cdr() -> RispExp {
 
 	    let l = match &args[0] {
 		RispExp::List(list) => list,
-		_ => return Err(RispErr::Reason("arg is not a list".to_string()))
+		_ => return Err(RispErr::Reason(format!("arg is not a list: {}", args[0])))
 	    };
 	    
 	    if l.len() < 1 {
@@ -55,33 +58,789 @@ pub fn cdr() -> RispExp {
     )
 }
 
-pub fn number_sequence() -> RispExp {
+pub fn cons_func() -> RispExp {
     RispExp::Func(
-      |args: &[RispExp]| -> Result<RispExp, RispErr> {
-	if args.len() < 1 {
-	  return Err(RispErr::Reason("pass a max value".to_string()));
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    match args {
+		[x, RispExp::List(l)] => {
+		    let mut res = Vec::with_capacity(l.len() + 1);
+		    res.push(x.clone());
+		    res.extend(l.iter().cloned());
+		    Ok(RispExp::List(res))
+		},
+		[_, _] => Err(RispErr::Reason("cdr must be a list".to_string())),
+		_ => Err(RispErr::Reason("pass an element and a list".to_string()))
+	    }
 	}
+    )
+}
 
-	let max = match args[0] {
-	  RispExp::Number(x) => x as i64,
-	  _ => return Err(RispErr::Reason("arg is not a number".to_string())),
-	};
+pub fn append_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    let mut res: Vec<RispExp> = Vec::new();
+	    for (i, arg) in args.iter().enumerate() {
+		match arg {
+		    RispExp::List(l) => res.extend(l.iter().cloned()),
+		    _ => return Err(RispErr::Reason(format!("arg {} is not a list: {}", i + 1, arg)))
+		}
+	    }
+	    Ok(RispExp::List(res))
+	}
+    )
+}
+
+pub fn repeat_value_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    match args {
+		[RispExp::Number(n), value] if *n >= 0.0 && n.fract() == 0.0 => {
+		    Ok(RispExp::List(vec![value.clone(); *n as usize]))
+		},
+		[n, _] => Err(RispErr::Reason(format!("count must be a non-negative integer: {}", n))),
+		_ => Err(RispErr::Reason("pass a count and a value".to_string()))
+	    }
+	}
+    )
+}
+
+// One item from each list in turn, stopping when the shortest runs out
+pub fn interleave_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    if args.len() < 2 {
+		return Err(RispErr::Reason("pass two or more lists".to_string()));
+	    }
+	    let mut lists: Vec<&Vec<RispExp>> = Vec::new();
+	    for (i, arg) in args.iter().enumerate() {
+		match arg {
+		    RispExp::List(l) => lists.push(l),
+		    _ => return Err(RispErr::Reason(format!("arg {} is not a list: {}", i + 1, arg)))
+		}
+	    }
+	    let shortest = lists.iter().map(|l| l.len()).min().unwrap_or(0);
+	    let mut res: Vec<RispExp> = Vec::new();
+	    for i in 0..shortest {
+		res.extend(lists.iter().map(|l| l[i].clone()));
+	    }
+	    Ok(RispExp::List(res))
+	}
+    )
+}
+
+// Strings count chars rather than bytes, json counts like json-count
+pub fn length_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    let n = match args {
+		[RispExp::List(l)] => l.len(),
+		[RispExp::Str(s)] => s.chars().count(),
+		[RispExp::Json(data)] => jsontypes::count(data)?,
+		[RispExp::Range(range)] => range.len(),
+		[x] => return Err(RispErr::Reason(format!("arg has no length: {}", x))),
+		_ => return Err(RispErr::Reason("pass a list or a string".to_string()))
+	    };
+	    Ok(RispExp::Number(n as f64))
+	}
+    )
+}
+
+pub fn nth_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    let (i, l) = match args {
+		[RispExp::Number(i), RispExp::List(l)] => (*i, l),
+		[RispExp::Number(_), x] => return Err(RispErr::Reason(format!("arg is not a list: {}", x))),
+		[x, _] => return Err(RispErr::Reason(format!("arg is not a number: {}", x))),
+		_ => return Err(RispErr::Reason("pass an index and a list".to_string()))
+	    };
+	    if i < 0.0 || i.fract() != 0.0 || i >= l.len() as f64 {
+		return Err(RispErr::Reason(format!("index out of range: {} in a list of length {}", i, l.len())));
+	    }
+	    Ok(l[i as usize].clone())
+	}
+    )
+}
+
+// Every nth item starting with the first
+pub fn take_nth_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    match args {
+		[RispExp::Number(n), RispExp::List(l)] if *n >= 1.0 && n.fract() == 0.0 => {
+		    Ok(RispExp::List(l.iter().step_by(*n as usize).cloned().collect()))
+		},
+		[n, RispExp::List(_)] => Err(RispErr::Reason(format!("step must be a positive integer: {}", n))),
+		[_, x] => Err(RispErr::Reason(format!("arg is not a list: {}", x))),
+		_ => Err(RispErr::Reason("pass a step and a list".to_string()))
+	    }
+	}
+    )
+}
+
+// The half open range start..end as a new list, both bounds must be
+// in the list and start can't come after end
+pub fn subvec_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    let (l, start, end) = match args {
+		[RispExp::List(l), RispExp::Number(start), RispExp::Number(end)] => (l, *start, *end),
+		[RispExp::List(_), RispExp::Number(_), x] | [RispExp::List(_), x, _] => {
+		    return Err(RispErr::Reason(format!("arg is not a number: {}", x)))
+		},
+		[x, _, _] => return Err(RispErr::Reason(format!("arg is not a list: {}", x))),
+		_ => return Err(RispErr::Reason("pass a list, a start and an end".to_string()))
+	    };
+	    for bound in [start, end] {
+		if bound < 0.0 || bound.fract() != 0.0 || bound > l.len() as f64 {
+		    return Err(RispErr::Reason(format!("index out of range: {} in a list of length {}", bound, l.len())));
+		}
+	    }
+	    if start > end {
+		return Err(RispErr::Reason(format!("start {} is after end {}", start, end)));
+	    }
+	    Ok(RispExp::List(l[start as usize..end as usize].to_vec()))
+	}
+    )
+}
+
+// The order to put items with these keys in, ascending and stable, so
+// items with equal keys keep the order they came in
+fn sort_order(keys: &[RispExp]) -> Result<Vec<usize>, RispErr> {
+    let mut order: Vec<usize> = (0..keys.len()).collect();
+    if keys.iter().all(|x| matches!(x, RispExp::Number(_))) {
+	if let Some(nan) = keys.iter().find(|x| matches!(x, RispExp::Number(n) if n.is_nan())) {
+	    return Err(RispErr::Reason(format!("cannot sort {}", nan)));
+	}
+	order.sort_by(|a, b| match (&keys[*a], &keys[*b]) {
+	    (RispExp::Number(a), RispExp::Number(b)) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+	    _ => Ordering::Equal
+	});
+    } else if keys.iter().all(|x| matches!(x, RispExp::Str(_))) {
+	order.sort_by(|a, b| match (&keys[*a], &keys[*b]) {
+	    (RispExp::Str(a), RispExp::Str(b)) => a.cmp(b),
+	    _ => Ordering::Equal
+	});
+    } else if keys.iter().all(|x| matches!(x, RispExp::Number(_) | RispExp::Str(_))) {
+	return Err(RispErr::Reason("cannot sort mixed types".to_string()));
+    } else {
+	return Err(RispErr::Reason("can only sort numbers or strings".to_string()));
+    }
+    Ok(order)
+}
+
+pub fn sort_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    let l = match args {
+		[RispExp::List(l)] => l,
+		[x] => return Err(RispErr::Reason(format!("arg is not a list: {}", x))),
+		_ => return Err(RispErr::Reason("pass a list".to_string()))
+	    };
+	    let order = sort_order(l)?;
+	    Ok(RispExp::List(order.into_iter().map(|i| l[i].clone()).collect()))
+	}
+    )
+}
+
+// Sorts by the key the function returns for each item, the keys follow
+// the same rules as sort and the function is called once per item
+pub fn sort_by() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    let (f, list) = match args {
+		[f @ (RispExp::Func(_) | RispExp::Lambda(_)), RispExp::List(list)] => (f, list),
+		[RispExp::Func(_) | RispExp::Lambda(_), x] => return Err(RispErr::Reason(format!("arg is not a list: {}", x))),
+		[f, _] => return Err(RispErr::Reason(format!("not a function: {}", f))),
+		_ => return Err(RispErr::Reason("pass a key function and a list".to_string()))
+	    };
+	    let keys = list.iter()
+		.map(|x| apply_function(f, std::slice::from_ref(x)))
+		.collect::<Result<Vec<_>, _>>()?;
+	    let order = sort_order(&keys)?;
+	    Ok(RispExp::List(order.into_iter().map(|i| list[i].clone()).collect()))
+	}
+    )
+}
+
+pub fn first_or() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    match args {
+		[RispExp::List(l), default] => Ok(l.first().unwrap_or(default).clone()),
+		[x, _] => Err(RispErr::Reason(format!("arg is not a list: {}", x))),
+		_ => Err(RispErr::Reason("pass a list and a default".to_string()))
+	    }
+	}
+    )
+}
+
+// A function rather than a special form like repeat, so it can be
+// passed around and stored like any other function
+pub fn map() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    map_items(args, |_, _| ())
+	}
+    )
+}
+
+// done is told how many items have been mapped so far and how many
+// there are, so a caller that can see *progress* can report it
+pub fn map_items(args: &[RispExp], mut done: impl FnMut(usize, usize)) -> Result<RispExp, RispErr> {
+    let (f, list) = match args {
+	[f @ (RispExp::Func(_) | RispExp::Lambda(_)), RispExp::List(list)] => (f, list),
+	[RispExp::Func(_) | RispExp::Lambda(_), x] => return Err(RispErr::Reason(format!("arg is not a list: {}", x))),
+	[f, _] => return Err(RispErr::Reason(format!("not a function: {}", f))),
+	_ => return Err(RispErr::Reason("pass a function and a list".to_string()))
+    };
+    let mut results = Vec::with_capacity(list.len());
+    for (i, x) in list.iter().enumerate() {
+	results.push(apply_function(f, std::slice::from_ref(x))?);
+	done(i + 1, list.len());
+    }
+    Ok(RispExp::List(results))
+}
+
+// The predicate has to return true or false, anything else is an error
+pub fn filter() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    let (f, list) = match args {
+		[f @ (RispExp::Func(_) | RispExp::Lambda(_)), RispExp::List(list)] => (f, list),
+		[RispExp::Func(_) | RispExp::Lambda(_), x] => return Err(RispErr::Reason(format!("arg is not a list: {}", x))),
+		[f, _] => return Err(RispErr::Reason(format!("not a function: {}", f))),
+		_ => return Err(RispErr::Reason("pass a predicate and a list".to_string()))
+	    };
+	    let mut kept = Vec::new();
+	    for x in list {
+		match apply_function(f, std::slice::from_ref(x))? {
+		    RispExp::Bool(true) => kept.push(x.clone()),
+		    RispExp::Bool(false) => (),
+		    _ => return Err(RispErr::Reason("predicate must return a bool".to_string()))
+		}
+	    }
+	    Ok(RispExp::List(kept))
+	}
+    )
+}
+
+// map where each call returns a list and the lists are appended
+pub fn mapcat() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    let (f, list) = match args {
+		[f @ (RispExp::Func(_) | RispExp::Lambda(_)), RispExp::List(list)] => (f, list),
+		[RispExp::Func(_) | RispExp::Lambda(_), x] => return Err(RispErr::Reason(format!("arg is not a list: {}", x))),
+		[f, _] => return Err(RispErr::Reason(format!("not a function: {}", f))),
+		_ => return Err(RispErr::Reason("pass a function and a list".to_string()))
+	    };
+	    let mut res = Vec::new();
+	    for x in list {
+		match apply_function(f, std::slice::from_ref(x))? {
+		    RispExp::List(l) => res.extend(l),
+		    other => return Err(RispErr::Reason(format!("function must return a list: {}", other)))
+		}
+	    }
+	    Ok(RispExp::List(res))
+	}
+    )
+}
+
+// Calls the function with one item from each list in turn, stopping
+// when the shortest runs out
+pub fn zip_with() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    let (f, rest) = match args {
+		[f @ (RispExp::Func(_) | RispExp::Lambda(_)), rest @ ..] if rest.len() >= 2 => (f, rest),
+		[RispExp::Func(_) | RispExp::Lambda(_), ..] => return Err(RispErr::Reason("pass two or more lists".to_string())),
+		[f, ..] => return Err(RispErr::Reason(format!("not a function: {}", f))),
+		_ => return Err(RispErr::Reason("pass a function and two or more lists".to_string()))
+	    };
+	    let mut lists: Vec<&Vec<RispExp>> = Vec::new();
+	    for (i, arg) in rest.iter().enumerate() {
+		match arg {
+		    RispExp::List(l) => lists.push(l),
+		    _ => return Err(RispErr::Reason(format!("arg {} is not a list: {}", i + 2, arg)))
+		}
+	    }
+	    let shortest = lists.iter().map(|l| l.len()).min().unwrap_or(0);
+	    let mut res = Vec::new();
+	    for i in 0..shortest {
+		let call_args: Vec<RispExp> = lists.iter().map(|l| l[i].clone()).collect();
+		res.push(apply_function(f, &call_args)?);
+	    }
+	    Ok(RispExp::List(res))
+	}
+    )
+}
+
+// Structural equality for data, functions are never equal
+pub fn values_equal(a: &RispExp, b: &RispExp) -> bool {
+    match (a, b) {
+	(RispExp::Bool(x), RispExp::Bool(y)) => x == y,
+	(RispExp::Symbol(x), RispExp::Symbol(y)) => x == y,
+	(RispExp::Number(x), RispExp::Number(y)) => x == y,
+	(RispExp::Str(x), RispExp::Str(y)) => x == y,
+	(RispExp::List(x), RispExp::List(y)) => {
+	    x.len() == y.len() && x.iter().zip(y.iter()).all(|(x, y)| values_equal(x, y))
+	},
+	(RispExp::Json(x), RispExp::Json(y)) => x == y,
+	_ => false
+    }
+}
+
+// Find the pair in an assoc list whose string key matches, ignoring ASCII case
+pub fn find_pair_ignore_case<'a>(key: &str, alist: &'a [RispExp]) -> Option<&'a RispExp> {
+    alist.iter().find(|pair| match pair {
+	RispExp::List(kv) => match kv.first() {
+	    Some(RispExp::Str(k)) => k.eq_ignore_ascii_case(key),
+	    _ => false
+	},
+	_ => false
+    })
+}
+
+// The position of the pair in an assoc list whose key equals key
+pub fn alist_position(key: &RispExp, alist: &[RispExp]) -> Option<usize> {
+    alist.iter().position(|pair| match pair {
+	RispExp::List(kv) => kv.first().is_some_and(|k| values_equal(k, key)),
+	_ => false
+    })
+}
+
+pub fn assoc_ignore_case() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    if args.len() != 2 {
+		return Err(RispErr::Reason("pass a key and an assoc list".to_string()));
+	    }
+
+	    let key = match &args[0] {
+		RispExp::Str(s) => s,
+		_ => return Err(RispErr::Reason(format!("key is not a string: {}", args[0])))
+	    };
+	    let alist = match &args[1] {
+		RispExp::List(list) => list,
+		_ => return Err(RispErr::Reason(format!("arg is not a list: {}", args[1])))
+	    };
+
+	    match find_pair_ignore_case(key, alist) {
+		Some(pair) => Ok(pair.clone()),
+		None => Ok(RispExp::List(vec![]))
+	    }
+	}
+    )
+}
+
+// The numbers num would list, made one at a time as they're needed,
+// so the aggregates can run over a huge range in constant memory
+#[derive(Clone, Copy, PartialEq)]
+pub struct Range {
+    pub max: i64,
+    pub start: i64,
+    pub step: i64,
+}
+
+impl Range {
+    pub fn iter(&self) -> impl Iterator<Item = f64> {
+	let Range { max, start, step } = *self;
+	std::iter::successors(Some(start), move |m| m.checked_add(step))
+	    .take_while(move |m| (step > 0 && *m < max) || (step < 0 && *m > max))
+	    .map(|m| m as f64)
+    }
+
+    pub fn len(&self) -> usize {
+	let Range { max, start, step } = *self;
+	let span = if step > 0 { max as i128 - start as i128 } else { start as i128 - max as i128 };
+	if span <= 0 {
+	    return 0;
+	}
+	let step = (step as i128).abs();
+	((span + step - 1) / step) as usize
+    }
 
-	let start = if args.len() < 2 { 0 } else {
-	  match args[1] {
+    // The first and last numbers, so the smallest and largest are
+    // known without walking the range
+    pub fn ends(&self) -> Option<(f64, f64)> {
+	match self.len() {
+	    0 => None,
+	    n => Some((self.start as f64, (self.start as i128 + (n as i128 - 1) * self.step as i128) as f64))
+	}
+    }
+}
+
+// (num max [start [step]]) and (range max [start [step]]) take the same
+// arguments
+fn range_args(args: &[RispExp]) -> Result<Range, RispErr> {
+    if args.is_empty() {
+	return Err(RispErr::Reason("pass a max value".to_string()));
+    }
+
+    let max = match args[0] {
+	RispExp::Number(x) => x as i64,
+	_ => return Err(RispErr::Reason(format!("arg is not a number: {}", args[0]))),
+    };
+
+    let start = if args.len() < 2 { 0 } else {
+	match args[1] {
 	    RispExp::Number(x) => x as i64,
-	    _ => return Err(RispErr::Reason("arg is not a number".to_string())),
-	  }
-	};
+	    _ => return Err(RispErr::Reason(format!("arg is not a number: {}", args[1]))),
+	}
+    };
 
-	let mut res: Vec<RispExp> = Vec::new();
-	let r = start..max;
-	for m in r {
-	  res.push(RispExp::Number(m as f64));
+    let step = if args.len() < 3 { 1 } else {
+	match args[2] {
+	    RispExp::Number(x) => x as i64,
+	    _ => return Err(RispErr::Reason(format!("arg is not a number: {}", args[2]))),
+	}
+    };
+    // A step that never reaches max would go on forever
+    if step == 0 || (step < 0 && start < max) {
+	return Err(RispErr::Reason("invalid step".to_string()));
+    }
+    Ok(Range { max, start, step })
+}
+
+pub fn number_sequence() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    let range = range_args(args)?;
+	    Ok(RispExp::List(range.iter().map(RispExp::Number).collect()))
 	}
-	Ok(RispExp::List(res))
-      }
     )
 }
 
+pub fn range_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    Ok(RispExp::Range(range_args(args)?))
+	}
+    )
+}
+
+// Lists, ranges and JSON arrays and objects are collections, anything
+// else isn't
+pub fn coll_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    let is_coll = match args {
+		[RispExp::List(_) | RispExp::Range(_)] => true,
+		[RispExp::Json(v)] => v.is_array() || v.is_object(),
+		[_] => false,
+		_ => return Err(RispErr::Reason("pass a single value".to_string()))
+	    };
+	    Ok(RispExp::Bool(is_coll))
+	}
+    )
+}
+
+const PPRINT_WIDTH: usize = 80;
+
+// Lists that don't fit in the width are broken one element per line,
+// the elements lined up under the first one
+pub fn pprint_string(exp: &RispExp, indent: usize, width: usize) -> String {
+    let flat = exp.to_string();
+    let list = match exp {
+	RispExp::List(list) if !list.is_empty() && indent + flat.len() > width => list,
+	_ => return flat
+    };
+    let lines: Vec<String> = list.iter()
+	.map(|x| pprint_string(x, indent + 1, width))
+	.collect();
+    format!("({})", lines.join(&format!(",\n{}", " ".repeat(indent + 1))))
+}
+
+fn pprint_width(args: &[RispExp]) -> Result<usize, RispErr> {
+    match args.get(1) {
+	Some(RispExp::Number(w)) if *w >= 0.0 => Ok(*w as usize),
+	Some(w) => Err(RispErr::Reason(format!("width must be a positive number: {}", w))),
+	None => Ok(PPRINT_WIDTH)
+    }
+}
+
+pub fn pprint() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    let exp = args.first().ok_or(RispErr::Reason("pass a value".to_string()))?;
+	    println!("{}", pprint_string(exp, 0, pprint_width(args)?));
+	    Ok(RispExp::List(vec![]))
+	}
+    )
+}
+
+pub fn pprint_str() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    let exp = args.first().ok_or(RispErr::Reason("pass a value".to_string()))?;
+	    Ok(RispExp::Str(pprint_string(exp, 0, pprint_width(args)?)))
+	}
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math;
+    use crate::test_helpers::*;
+
+    #[test]
+    fn list_car_and_cdr() {
+	assert_eq!(call(list(), &[n(1.0), n(2.0), n(3.0)]), Ok("(1,2,3)".to_string()));
+	assert_eq!(call(car(), &[nums(&[1.0, 2.0, 3.0])]), Ok("1".to_string()));
+	assert_eq!(call(cdr(), &[nums(&[1.0, 2.0, 3.0])]), Ok("(2,3)".to_string()));
+	assert_eq!(call(car(), &[l(vec![s("a"), s("b")])]), Ok("\"a\"".to_string()));
+	assert_eq!(call(cdr(), &[nums(&[])]), Err("empty list".to_string()));
+	assert_eq!(call(car(), &[n(5.0)]), Err("arg is not a list: 5".to_string()));
+    }
+
+    #[test]
+    fn assoc_ignore_case_finds_pairs() {
+	let headers = l(vec![l(vec![s("Content-Type"), s("text/plain")])]);
+	assert_eq!(
+	    call(assoc_ignore_case(), &[s("content-type"), headers.clone()]),
+	    Ok("(\"Content-Type\",\"text/plain\")".to_string())
+	);
+	assert_eq!(call(assoc_ignore_case(), &[s("accept"), headers]), Ok("()".to_string()));
+    }
+
+    #[test]
+    fn pprint_breaks_nested_lists() {
+	let nested = l(vec![nums(&[1.0, 2.0]), nums(&[3.0, 4.0])]);
+	assert_eq!(pprint_string(&nested, 0, 80), "((1,2),(3,4))");
+	assert_eq!(pprint_string(&nested, 0, 8), "((1,2),\n (3,4))");
+	assert_eq!(pprint_string(&nested, 0, 4), "((1,\n  2),\n (3,\n  4))");
+    }
+
+    #[test]
+    fn values_equal_is_structural() {
+	assert!(values_equal(&nums(&[1.0, 2.0]), &nums(&[1.0, 2.0])));
+	assert!(!values_equal(&nums(&[1.0]), &l(vec![s("1")])));
+	assert!(!values_equal(&car(), &car()));
+    }
+
+    #[test]
+    fn sort_by_keeps_ties_in_order() {
+	let records = l(vec![
+	    l(vec![n(2.0), s("a")]),
+	    l(vec![n(1.0), s("b")]),
+	    l(vec![n(2.0), s("c")]),
+	    l(vec![n(1.0), s("d")]),
+	    l(vec![n(0.0), s("e")])
+	]);
+	assert_eq!(
+	    call(sort_by(), &[car(), records]),
+	    Ok("((0,\"e\"),(1,\"b\"),(1,\"d\"),(2,\"a\"),(2,\"c\"))".to_string())
+	);
+    }
+
+    #[test]
+    fn sort_by_checks_its_keys() {
+	let words = l(vec![s("b"), s("a")]);
+	assert_eq!(call(sort_by(), &[car(), words.clone()]), Err("arg is not a list: \"b\"".to_string()));
+	let mixed = l(vec![l(vec![n(1.0)]), l(vec![s("a")])]);
+	assert_eq!(call(sort_by(), &[car(), mixed]), Err("cannot sort mixed types".to_string()));
+	assert_eq!(call(sort_by(), &[n(1.0), words]), Err("not a function: 1".to_string()));
+    }
+
+    #[test]
+    fn coll_across_types() {
+	let json_array = RispExp::Json(std::rc::Rc::new(serde_json::json!([1, 2])));
+	let json_object = RispExp::Json(std::rc::Rc::new(serde_json::json!({"a": 1})));
+	let json_scalar = RispExp::Json(std::rc::Rc::new(serde_json::json!(1)));
+	assert_eq!(call(coll_func(), &[nums(&[1.0])]), Ok("true".to_string()));
+	assert_eq!(call(coll_func(), &[json_array]), Ok("true".to_string()));
+	assert_eq!(call(coll_func(), &[json_object]), Ok("true".to_string()));
+	assert_eq!(call(coll_func(), &[json_scalar]), Ok("false".to_string()));
+	assert_eq!(call(coll_func(), &[n(1.0)]), Ok("false".to_string()));
+	assert_eq!(call(coll_func(), &[s("ab")]), Ok("false".to_string()));
+    }
+
+    #[test]
+    fn alist_position_compares_keys() {
+	let alist = [l(vec![s("a"), n(1.0)]), l(vec![n(2.0), n(3.0)])];
+	assert_eq!(alist_position(&s("a"), &alist), Some(0));
+	assert_eq!(alist_position(&n(2.0), &alist), Some(1));
+	assert_eq!(alist_position(&s("b"), &alist), None);
+    }
+
+    #[test]
+    fn first_or_defaults() {
+	assert_eq!(call(first_or(), &[nums(&[1.0, 2.0]), n(0.0)]), Ok("1".to_string()));
+	assert_eq!(call(first_or(), &[nums(&[]), n(0.0)]), Ok("0".to_string()));
+    }
+
+    #[test]
+    fn range_has_the_numbers_num_lists() {
+	for args in [&[10.0][..], &[10.0, 3.0], &[10.0, 0.0, 3.0], &[0.0, 10.0, -3.0], &[0.0], &[5.0, 10.0]] {
+	    let range = match call_exp(range_func(), &numbers(args)) {
+		Ok(RispExp::Range(range)) => range,
+		_ => panic!("not a range")
+	    };
+	    let listed = call(number_sequence(), &numbers(args)).unwrap();
+	    assert_eq!(l(range.iter().map(RispExp::Number).collect()).to_string(), listed);
+	    assert_eq!(range.len(), range.iter().count());
+	}
+	assert_eq!(call(range_func(), &numbers(&[0.0, 5.0, -1.0])), Ok("(range 0 5 -1)".to_string()));
+	assert_eq!(call(range_func(), &numbers(&[5.0, 0.0, 0.0])), Err("invalid step".to_string()));
+    }
+
+    #[test]
+    fn map_takes_builtins() {
+	assert_eq!(call(map(), &[car(), l(vec![nums(&[1.0, 2.0]), nums(&[3.0])])]), Ok("(1,3)".to_string()));
+	assert_eq!(call(map(), &[n(1.0), nums(&[1.0])]), Err("not a function: 1".to_string()));
+	assert_eq!(call(map(), &[car(), n(1.0)]), Err("arg is not a list: 1".to_string()));
+    }
+
+    #[test]
+    fn filter_takes_builtins() {
+	assert_eq!(call(filter(), &[math::even_func(), nums(&[1.0, 2.0, 3.0, 4.0])]), Ok("(2,4)".to_string()));
+	assert_eq!(
+	    call(filter(), &[car(), l(vec![nums(&[1.0])])]),
+	    Err("predicate must return a bool".to_string())
+	);
+    }
+
+    #[test]
+    fn cons_prepends() {
+	assert_eq!(call(cons_func(), &[n(0.0), nums(&[1.0, 2.0])]), Ok("(0,1,2)".to_string()));
+	assert_eq!(call(cons_func(), &[n(0.0), n(1.0)]), Err("cdr must be a list".to_string()));
+    }
+
+    #[test]
+    fn cons_then_car_round_trips() {
+	let consed = call_exp(cons_func(), &[n(9.0), nums(&[1.0, 2.0])]).unwrap();
+	assert_eq!(call(car(), &[consed]), Ok("9".to_string()));
+    }
+
+    #[test]
+    fn append_lists() {
+	let lists = [nums(&[1.0, 2.0]), nums(&[3.0, 4.0]), nums(&[5.0])];
+	assert_eq!(call(append_func(), &lists), Ok("(1,2,3,4,5)".to_string()));
+	assert_eq!(call(append_func(), &[]), Ok("()".to_string()));
+	assert_eq!(call(append_func(), &[nums(&[1.0]), n(2.0)]), Err("arg 2 is not a list: 2".to_string()));
+    }
+
+    #[test]
+    fn length_of_lists_strings_and_json() {
+	assert_eq!(call(length_func(), &[nums(&[0.0, 1.0, 2.0])]), Ok("3".to_string()));
+	assert_eq!(call(length_func(), &[s("hello")]), Ok("5".to_string()));
+	assert_eq!(call(length_func(), &[s("héllo wörld")]), Ok("11".to_string()));
+	assert_eq!(call(length_func(), &[n(1.0)]), Err("arg has no length: 1".to_string()));
+    }
+
+    #[test]
+    fn nth_boundaries() {
+	let xs = nums(&[10.0, 20.0, 30.0]);
+	assert_eq!(call(nth_func(), &[n(0.0), xs.clone()]), Ok("10".to_string()));
+	assert_eq!(call(nth_func(), &[n(2.0), xs.clone()]), Ok("30".to_string()));
+	assert_eq!(
+	    call(nth_func(), &[n(3.0), xs.clone()]),
+	    Err("index out of range: 3 in a list of length 3".to_string())
+	);
+	assert!(call(nth_func(), &[n(-1.0), xs]).is_err());
+    }
+
+    #[test]
+    fn num_with_each_arity() {
+	assert_eq!(call(number_sequence(), &[n(3.0)]), Ok("(0,1,2)".to_string()));
+	assert_eq!(call(number_sequence(), &[n(5.0), n(2.0)]), Ok("(2,3,4)".to_string()));
+	assert_eq!(call(number_sequence(), &[n(10.0), n(0.0), n(2.0)]), Ok("(0,2,4,6,8)".to_string()));
+	assert_eq!(call(number_sequence(), &[n(0.0), n(5.0), n(-2.0)]), Ok("(5,3,1)".to_string()));
+	assert_eq!(call(number_sequence(), &[n(10.0), n(0.0), n(0.0)]), Err("invalid step".to_string()));
+	assert_eq!(call(number_sequence(), &[n(10.0), n(0.0), n(-1.0)]), Err("invalid step".to_string()));
+    }
+
+    #[test]
+    fn subvec_slices() {
+	let xs = nums(&[10.0, 20.0, 30.0, 40.0]);
+	assert_eq!(call(subvec_func(), &[xs.clone(), n(1.0), n(3.0)]), Ok("(20,30)".to_string()));
+	assert_eq!(call(subvec_func(), &[xs.clone(), n(0.0), n(4.0)]), Ok("(10,20,30,40)".to_string()));
+	assert_eq!(
+	    call(subvec_func(), &[xs.clone(), n(2.0), n(5.0)]),
+	    Err("index out of range: 5 in a list of length 4".to_string())
+	);
+	assert!(call(subvec_func(), &[xs.clone(), n(-1.0), n(2.0)]).is_err());
+	assert_eq!(call(subvec_func(), &[xs, n(3.0), n(1.0)]), Err("start 3 is after end 1".to_string()));
+    }
+
+    #[test]
+    fn sort_numbers_and_strings() {
+	assert_eq!(call(sort_func(), &[nums(&[3.0, 1.0, 2.0])]), Ok("(1,2,3)".to_string()));
+	assert_eq!(
+	    call(sort_func(), &[l(vec![s("pear"), s("apple"), s("fig")])]),
+	    Ok("(\"apple\",\"fig\",\"pear\")".to_string())
+	);
+	assert_eq!(call(sort_func(), &[l(vec![n(1.0), s("a")])]), Err("cannot sort mixed types".to_string()));
+	assert_eq!(call(sort_func(), &[nums(&[1.0, f64::NAN])]), Err("cannot sort NaN".to_string()));
+    }
+
+    #[test]
+    fn interleave_lists() {
+	let letters = l(vec![s("a"), s("b"), s("c")]);
+	assert_eq!(
+	    call(interleave_func(), &[nums(&[1.0, 2.0, 3.0]), letters.clone()]),
+	    Ok("(1,\"a\",2,\"b\",3,\"c\")".to_string())
+	);
+	assert_eq!(
+	    call(interleave_func(), &[nums(&[1.0, 2.0, 3.0]), l(vec![s("a")])]),
+	    Ok("(1,\"a\")".to_string())
+	);
+	assert_eq!(
+	    call(interleave_func(), &[nums(&[1.0, 2.0]), letters, nums(&[7.0, 8.0, 9.0])]),
+	    Ok("(1,\"a\",7,2,\"b\",8)".to_string())
+	);
+    }
+
+    #[test]
+    fn repeat_value_counts() {
+	assert_eq!(call(repeat_value_func(), &[n(0.0), s("x")]), Ok("()".to_string()));
+	assert_eq!(call(repeat_value_func(), &[n(3.0), s("x")]), Ok("(\"x\",\"x\",\"x\")".to_string()));
+	assert!(call(repeat_value_func(), &[n(-1.0), s("x")]).is_err());
+    }
+
+    #[test]
+    fn repeat_value_copies_are_independent() {
+	let repeated = call_exp(repeat_value_func(), &[n(2.0), nums(&[1.0])]).unwrap();
+	let mut copies = match repeated {
+	    RispExp::List(copies) => copies,
+	    _ => panic!("not a list")
+	};
+	if let RispExp::List(first) = &mut copies[0] {
+	    first.push(n(2.0));
+	}
+	assert_eq!(l(copies).to_string(), "((1,2),(1))");
+    }
+
+    #[test]
+    fn take_nth_steps() {
+	let xs = nums(&[0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0]);
+	assert_eq!(call(take_nth_func(), &[n(1.0), nums(&[0.0, 1.0, 2.0])]), Ok("(0,1,2)".to_string()));
+	assert_eq!(call(take_nth_func(), &[n(2.0), xs.clone()]), Ok("(0,2,4,6,8)".to_string()));
+	assert_eq!(call(take_nth_func(), &[n(0.0), xs]), Err("step must be a positive integer: 0".to_string()));
+    }
+
+    #[test]
+    fn mapcat_appends_results() {
+	let pairs = l(vec![nums(&[1.0, 1.0]), nums(&[]), nums(&[2.0, 2.0])]);
+	assert_eq!(call(mapcat(), &[cdr(), l(vec![nums(&[0.0, 1.0, 1.0]), nums(&[0.0])])]), Ok("(1,1)".to_string()));
+	assert_eq!(call(mapcat(), &[list(), pairs]), Ok("((1,1),(),(2,2))".to_string()));
+	assert_eq!(call(mapcat(), &[car(), l(vec![nums(&[1.0])])]), Err("function must return a list: 1".to_string()));
+    }
+
+    #[test]
+    fn zip_with_stops_at_the_shortest() {
+	assert_eq!(
+	    call(zip_with(), &[math::plus_func(), nums(&[1.0, 2.0]), nums(&[10.0, 20.0])]),
+	    Ok("(11,22)".to_string())
+	);
+	assert_eq!(
+	    call(zip_with(), &[math::plus_func(), nums(&[1.0, 2.0, 3.0]), nums(&[10.0, 20.0]), nums(&[100.0, 200.0])]),
+	    Ok("(111,222)".to_string())
+	);
+	assert_eq!(call(zip_with(), &[math::plus_func(), nums(&[1.0])]), Err("pass two or more lists".to_string()));
+    }
+}
+
 // End
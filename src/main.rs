@@ -1,9 +1,22 @@
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::collections::HashSet;
 use std::fmt;
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
 use std::io;
+use std::io::IsTerminal;
+use std::io::Read;
 use std::io::Write;
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
 use std::num::ParseFloatError;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
 use serde_json;
 
 trait RispValueString {
@@ -19,18 +32,29 @@ pub enum RispExp {
   List(Vec<RispExp>),
   Func(fn(&[RispExp]) -> Result<RispExp, RispErr>),
   Lambda(RispLambda),
-  Json(serde_json::Value)
+  // Rc so that evaluating a big json binding doesn't deep copy it
+  Json(Rc<serde_json::Value>),
+  Range(lists::Range),
 }
 
 mod lists;
 mod math;
 mod http;
 mod jsontypes;
+mod strings;
+mod regexp;
+mod csv;
+mod detach;
+#[cfg(test)]
+mod test_helpers;
 
 #[derive(Clone)]
 pub struct RispLambda {
   params_exp: Rc<RispExp>,
-  body_exp: Rc<RispExp>,
+  // evaluated in order, the value of the last is the result
+  body_exps: Rc<Vec<RispExp>>,
+  // the env the lambda was made in, free variables are looked up here
+  env: RispEnv,
 }
 
 // Conventional rust to_string used for printable form
@@ -39,7 +63,7 @@ impl fmt::Display for RispExp {
     let str = match self {
       RispExp::Bool(a) => a.to_string(),
       RispExp::Symbol(s) => s.clone(),
-      RispExp::Number(n) => n.to_string(),
+      RispExp::Number(n) => math::display_number(*n),
       RispExp::Str(s) => format!("\"{}\"", s),
       RispExp::List(list) => {
         let xs: Vec<String> = list
@@ -53,7 +77,9 @@ impl fmt::Display for RispExp {
       },
       RispExp::Func(_) => "Function {}".to_string(),
       RispExp::Lambda(_) => "Lambda {}".to_string(),
-      RispExp::Json(data) => jsontypes::display(&data),
+      RispExp::Json(data) => jsontypes::display(data),
+      // the call that makes it, printing the numbers would realize it
+      RispExp::Range(r) => format!("(range {} {} {})", r.max, r.start, r.step),
     };
     
     write!(f, "{}", str)
@@ -75,10 +101,24 @@ pub enum RispErr {
   Reason(String),
 }
 
+// Cloning an env shares it, so a lambda holding on to the env it was
+// made in sees later defs there
 #[derive(Clone)]
-struct RispEnv<'a> {
-  data: HashMap<String, RispExp>,
-  outer: Option<&'a RispEnv<'a>>,
+struct RispEnv {
+  data: Rc<RefCell<HashMap<String, RispExp>>>,
+  // keys of data made with defconst, which def won't overwrite
+  consts: Rc<RefCell<HashSet<String>>>,
+  outer: Option<Rc<RispEnv>>,
+}
+
+impl RispEnv {
+  fn new(data: HashMap<String, RispExp>, outer: Option<&RispEnv>) -> RispEnv {
+    RispEnv {
+      data: Rc::new(RefCell::new(data)),
+      consts: Rc::new(RefCell::new(HashSet::new())),
+      outer: outer.map(|env| Rc::new(env.clone())),
+    }
+  }
 }
 
 
@@ -86,11 +126,27 @@ struct RispEnv<'a> {
   Parse
 */
 
-fn tokenize(expr: String) -> Vec<String> {
+// The character a backslash escape in a string stands for, anything
+// not listed keeps its backslash so regexes like "\d+" still work
+fn unescape(c: char) -> String {
+  match c {
+    'n' => "\n".to_string(),
+    't' => "\t".to_string(),
+    'r' => "\r".to_string(),
+    '"' | '\\' => c.to_string(),
+    _ => format!("\\{}", c),
+  }
+}
+
+fn tokenize(expr: String) -> Result<Vec<String>, RispErr> {
   let mut tokens = Vec::new();
   let mut buf_str = String::new();
   let mut in_quote = false;
+  let mut in_escape = false;
   let mut in_comment = false;
+  // |quoted symbols| keep their leading `|` as a token so parse_atom
+  // knows not to treat them as anything but a symbol
+  let mut in_pipe = false;
 
   for c in expr.chars() {
     if in_comment && c != '\n' {
@@ -102,11 +158,43 @@ fn tokenize(expr: String) -> Vec<String> {
       continue;
     }
 
-    if (c == ';' || c == '#') && !in_quote {
+    if (c == ';' || c == '#') && !in_quote && !in_pipe {
       in_comment = true;
       continue;
     }
+
+    if c == '|' && in_pipe {
+      tokens.push(buf_str);
+      in_pipe = false;
+      buf_str = String::new();
+      continue;
+    }
+
+    if in_pipe {
+      buf_str.push(c);
+      continue;
+    }
+
+    if c == '|' && !in_quote {
+      in_pipe = true;
+      if !buf_str.is_empty() {
+	tokens.push(buf_str);
+      }
+      buf_str = String::from("|");
+      continue;
+    }
     
+    if in_escape {
+      buf_str.push_str(&unescape(c));
+      in_escape = false;
+      continue;
+    }
+
+    if c == '\\' && in_quote {
+      in_escape = true;
+      continue;
+    }
+
     if c == '"' && in_quote {
       buf_str.push('"');
       tokens.push(buf_str);
@@ -129,6 +217,17 @@ fn tokenize(expr: String) -> Vec<String> {
       continue;
     }
 
+    if (c == '\'' || c == '`' || c == ',') && buf_str.is_empty() {
+      tokens.push(c.to_string());
+      continue;
+    }
+
+    if c == '@' && buf_str.is_empty() && tokens.last().is_some_and(|t| t == ",") {
+      tokens.pop();
+      tokens.push(",@".to_string());
+      continue;
+    }
+
     if c == '(' || c == ')' {
       if buf_str.len() > 0 {
 	tokens.push(buf_str);
@@ -149,6 +248,14 @@ fn tokenize(expr: String) -> Vec<String> {
     buf_str.push(c);
   }
 
+  if in_pipe {
+    return Err(RispErr::Reason("unterminated `|` in symbol".to_string()));
+  }
+
+  if in_quote {
+    return Err(RispErr::Reason("unterminated string".to_string()));
+  }
+
   if buf_str.len() > 0 {
     tokens.push(buf_str);
   }
@@ -159,7 +266,7 @@ fn tokenize(expr: String) -> Vec<String> {
     }
   }
 
-  tokens
+  Ok(tokens)
 }
 
 fn parse<'a>(tokens: &'a [String]) -> Result<(RispExp, &'a [String]), RispErr> {
@@ -170,6 +277,16 @@ fn parse<'a>(tokens: &'a [String]) -> Result<(RispExp, &'a [String]), RispErr> {
   match &token[..] {
     "(" => read_seq(rest),
     ")" => Err(RispErr::Reason("unexpected `)`".to_string())),
+    "'" | "`" | "," | ",@" => {
+      let name = match &token[..] {
+        "'" => "quote",
+        "`" => "quasiquote",
+        "," => "unquote",
+        _ => "unquote-splicing",
+      };
+      let (exp, rest) = parse(rest)?;
+      Ok((RispExp::List(vec![RispExp::Symbol(name.to_string()), exp]), rest))
+    },
     _ => Ok((parse_atom(token), rest)),
   }
 }
@@ -191,16 +308,30 @@ fn read_seq<'a>(tokens: &'a [String]) -> Result<(RispExp, &'a [String]), RispErr
   }
 }
 
+// A number is an optional sign then a digit, or a `.` and a digit, so
+// `-3` and `-.5` are numbers but `-`, `->foo` and `inf` are symbols
+fn looks_like_number(token: &str) -> bool {
+  let unsigned = token.strip_prefix(['-', '+']).unwrap_or(token);
+  let digits = unsigned.strip_prefix('.').unwrap_or(unsigned);
+  digits.starts_with(|c: char| c.is_ascii_digit())
+}
+
 fn parse_atom(token: &str) -> RispExp {
   match token.as_ref() {
     "true" => RispExp::Bool(true),
     "false" => RispExp::Bool(false),
     _ => {
+      if let Some(name) = token.strip_prefix('|') {
+        return RispExp::Symbol(name.to_string());
+      }
       if token.len() > 0 && token.chars().nth(0).unwrap() == '"' {
 	let s = token.to_string();
 	let val = &s[1..s.len() - 1];
 	return RispExp::Str(val.to_string());
       }
+      if !looks_like_number(token) {
+        return RispExp::Symbol(token.to_string());
+      }
       let potential_float: Result<f64, ParseFloatError> = token.parse();
       match potential_float {
         Ok(v) => RispExp::Number(v),
@@ -231,23 +362,207 @@ macro_rules! ensure_tonicity {
   }};
 }
 
-fn default_env<'a>() -> RispEnv<'a> {
+// What doc says about each builtin, kept next to default_env so a
+// new builtin gets its line at the same time
+const BUILTIN_DOCS: &[(&str, &str)] = &[
+  ("httpget", "fetches a url, returning the status, headers and body"),
+  ("header", "looks up a header in an httpget response"),
+  ("response-lines", "returns the lines of an httpget response body"),
+  ("jget", "looks up a key or index in a json value"),
+  ("get", "looks up a key or index in a json value"),
+  ("json-path", "returns the values matching a json path"),
+  ("json-set", "returns a json value with the value at a path replaced"),
+  ("json-count", "returns the number of elements in a json array or object"),
+  ("json-stringify", "returns a value as a json string"),
+  ("list->json", "turns a list into json, lists of pairs become objects"),
+  ("contains-key?", "tests whether a json value or assoc list has a key"),
+  ("pairs", "returns the (key value) pairs of a json object"),
+  ("map-to-pairs", "returns the (key value) pairs of a json object"),
+  ("map-from-pairs", "makes a json object from a list of (key value) pairs"),
+  ("get-in", "looks up a path of keys and indexes"),
+  ("get-or", "looks up a key, returning a default if it's missing"),
+  ("deep-merge", "merges two json objects or assoc lists all the way down"),
+  ("slurp-json", "reads json from a file"),
+  ("spit-json", "writes a value to a file as json"),
+  ("num", "returns a list of numbers from start up to max"),
+  ("range", "like num but lazy, the numbers are made as they're needed"),
+  ("list", "returns a list of its arguments"),
+  ("car", "returns the first element of a list"),
+  ("cdr", "returns all but the first element of a list"),
+  ("cons", "returns a list with a value added to the front"),
+  ("append", "joins lists into one list"),
+  ("length", "returns the length of a list, string or json value"),
+  ("count", "returns the length of a list, string or json value"),
+  ("nth", "returns the element of a list at an index"),
+  ("take-nth", "returns every nth element of a list"),
+  ("interleave", "takes an element from each list in turn"),
+  ("repeat-value", "returns a list of a value repeated n times"),
+  ("subvec", "returns the elements of a list between two indexes"),
+  ("sort", "sorts a list of numbers or strings"),
+  ("sort-by", "sorts a list by the key a function returns for each element"),
+  ("first-or", "returns the first element of a list or a default"),
+  ("map", "applies a function to every element of a list"),
+  ("filter", "keeps the elements of a list a predicate is true for"),
+  ("mapcat", "maps a function returning lists and appends the results"),
+  ("zip-with", "calls a function with an item from each list in turn"),
+  ("coll?", "tests whether a value is a list, range or json collection"),
+  ("assoc-ignore-case", "looks up a key in an assoc list ignoring case"),
+  ("pprint", "prints a value over several lines"),
+  ("pprint-str", "returns a value formatted over several lines"),
+  ("string-eq-ignore-case", "compares two strings ignoring case"),
+  ("str-index-of", "returns the position of the first match in a string"),
+  ("str-last-index-of", "returns the position of the last match in a string"),
+  ("substring", "returns the characters of a string between two indexes"),
+  ("char-code", "returns the code point of a character"),
+  ("code-char", "returns the character for a code point"),
+  ("str-lines", "splits a string into lines"),
+  ("str-words", "splits a string into words"),
+  ("split", "splits a string at a separator"),
+  ("join", "joins a list into a string with a separator"),
+  ("upcase", "returns a string in upper case"),
+  ("downcase", "returns a string in lower case"),
+  ("trim", "returns a string without whitespace at either end"),
+  ("concat", "joins strings into a string or lists into a list"),
+  ("regex-match", "returns the first match of a regex in a string, or nil"),
+  ("regex-find-all", "returns every match of a regex in a string"),
+  ("regex-replace", "replaces every match of a regex in a string"),
+  ("regex-captures", "returns the capture groups of a regex match"),
+  ("parse-csv", "parses csv text into a list of rows"),
+  ("write-csv", "writes a list of rows as csv text"),
+  ("*", "multiplies numbers"),
+  ("+", "adds numbers"),
+  ("-", "subtracts numbers from the first"),
+  ("/", "divides the first number by the rest"),
+  ("mod", "returns the remainder of dividing two numbers"),
+  ("quotient", "divides two numbers rounding towards zero"),
+  ("max", "returns the largest number or string"),
+  ("min", "returns the smallest number or string"),
+  ("positive?", "tests whether a number is more than zero"),
+  ("negative?", "tests whether a number is less than zero"),
+  ("zero?", "tests whether a number is zero"),
+  ("even?", "tests whether an integer is even"),
+  ("odd?", "tests whether an integer is odd"),
+  ("clamp", "limits a number to a min and a max"),
+  ("in-range?", "tests whether a number is between a min and a max"),
+  ("approx=", "tests whether two numbers are within a tolerance"),
+  ("sum", "adds up a list of numbers or a range"),
+  ("mean", "returns the average of a list of numbers"),
+  ("median", "returns the middle of a list of numbers"),
+  ("mode", "returns the most common number in a list"),
+  ("stddev", "returns the standard deviation of a list of numbers"),
+  ("sleep", "waits for a number of milliseconds, returning nil"),
+  ("not", "returns true for false values and false for true ones"),
+  ("truthy?", "tests whether a value counts as true"),
+  ("falsy?", "tests whether a value counts as false"),
+  ("=", "tests whether values are equal"),
+  (">", "tests whether numbers are decreasing"),
+  (">=", "tests whether numbers are not increasing"),
+  ("<", "tests whether numbers are increasing"),
+  ("<=", "tests whether numbers are not decreasing"),
+];
+
+fn builtin_doc(name: &str) -> Option<&'static str> {
+  BUILTIN_DOCS.iter().find(|(k, _)| *k == name).map(|(_, doc)| *doc)
+}
+
+fn default_env() -> RispEnv {
   let mut data: HashMap<String, RispExp> = HashMap::new();
   data.insert("httpget".to_string(), http::httpget_func());
+  data.insert("header".to_string(), http::header_func());
+  data.insert("response-lines".to_string(), http::response_lines_func());
   data.insert("jget".to_string(), jsontypes::get_func());
+  data.insert("get".to_string(), jsontypes::get_func());
+  data.insert("json-path".to_string(), jsontypes::path_func());
+  data.insert("json-set".to_string(), jsontypes::set_func());
+  data.insert("json-count".to_string(), jsontypes::count_func());
+  data.insert("json-stringify".to_string(), jsontypes::stringify_func());
+  data.insert("list->json".to_string(), jsontypes::list_to_json_func());
+  data.insert("contains-key?".to_string(), jsontypes::contains_key_func());
+  data.insert("pairs".to_string(), jsontypes::pairs_func());
+  data.insert("map-to-pairs".to_string(), jsontypes::pairs_func());
+  data.insert("map-from-pairs".to_string(), jsontypes::map_from_pairs_func());
+  data.insert("get-in".to_string(), jsontypes::get_in_func());
+  data.insert("get-or".to_string(), jsontypes::get_or_func());
+  data.insert("deep-merge".to_string(), jsontypes::deep_merge_func());
+  data.insert("slurp-json".to_string(), jsontypes::slurp_func());
+  data.insert("spit-json".to_string(), jsontypes::spit_func());
   data.insert("num".to_string(), lists::number_sequence());
+  data.insert("range".to_string(), lists::range_func());
   data.insert("list".to_string(), lists::list());
   data.insert("car".to_string(), lists::car());
   data.insert("cdr".to_string(), lists::cdr());
+  data.insert("cons".to_string(), lists::cons_func());
+  data.insert("append".to_string(), lists::append_func());
+  data.insert("length".to_string(), lists::length_func());
+  data.insert("count".to_string(), lists::length_func());
+  data.insert("nth".to_string(), lists::nth_func());
+  data.insert("take-nth".to_string(), lists::take_nth_func());
+  data.insert("interleave".to_string(), lists::interleave_func());
+  data.insert("repeat-value".to_string(), lists::repeat_value_func());
+  data.insert("subvec".to_string(), lists::subvec_func());
+  data.insert("sort".to_string(), lists::sort_func());
+  data.insert("sort-by".to_string(), lists::sort_by());
+  data.insert("first-or".to_string(), lists::first_or());
+  data.insert("map".to_string(), lists::map());
+  data.insert("filter".to_string(), lists::filter());
+  data.insert("mapcat".to_string(), lists::mapcat());
+  data.insert("zip-with".to_string(), lists::zip_with());
+  data.insert("coll?".to_string(), lists::coll_func());
+  data.insert("assoc-ignore-case".to_string(), lists::assoc_ignore_case());
+  data.insert("pprint".to_string(), lists::pprint());
+  data.insert("pprint-str".to_string(), lists::pprint_str());
+  data.insert("string-eq-ignore-case".to_string(), strings::string_eq_ignore_case());
+  data.insert("str-index-of".to_string(), strings::index_of());
+  data.insert("str-last-index-of".to_string(), strings::last_index_of());
+  data.insert("substring".to_string(), strings::substring_func());
+  data.insert("char-code".to_string(), strings::char_code());
+  data.insert("code-char".to_string(), strings::code_char());
+  data.insert("str-lines".to_string(), strings::lines_func());
+  data.insert("str-words".to_string(), strings::words_func());
+  data.insert("split".to_string(), strings::split_func());
+  data.insert("join".to_string(), strings::join_func());
+  data.insert("upcase".to_string(), strings::upcase_func());
+  data.insert("downcase".to_string(), strings::downcase_func());
+  data.insert("trim".to_string(), strings::trim_func());
+  data.insert("concat".to_string(), strings::concat_func());
+  data.insert("regex-match".to_string(), regexp::match_func());
+  data.insert("regex-find-all".to_string(), regexp::find_all_func());
+  data.insert("regex-replace".to_string(), regexp::replace_func());
+  data.insert("regex-captures".to_string(), regexp::captures_func());
+  data.insert("parse-csv".to_string(), csv::parse_func());
+  data.insert("write-csv".to_string(), csv::write_func());
+  data.insert("nil".to_string(), RispExp::List(vec![]));
   data.insert("*".to_string(), math::mult_func());
   data.insert("+".to_string(), math::plus_func());
   data.insert("-".to_string(), math::minus_func());
-  data.insert("=".to_string(), RispExp::Func(ensure_tonicity!(|a, b| a == b)));
+  data.insert("/".to_string(), math::div_func());
+  data.insert("mod".to_string(), math::mod_func());
+  data.insert("quotient".to_string(), math::quotient_func());
+  data.insert("max".to_string(), math::max_func());
+  data.insert("min".to_string(), math::min_func());
+  data.insert("positive?".to_string(), math::positive_func());
+  data.insert("negative?".to_string(), math::negative_func());
+  data.insert("zero?".to_string(), math::zero_func());
+  data.insert("even?".to_string(), math::even_func());
+  data.insert("odd?".to_string(), math::odd_func());
+  data.insert("clamp".to_string(), math::clamp_func());
+  data.insert("in-range?".to_string(), math::in_range_func());
+  data.insert("approx=".to_string(), math::approx_equal_func());
+  data.insert("sum".to_string(), math::sum_func());
+  data.insert("mean".to_string(), math::mean_func());
+  data.insert("median".to_string(), math::median_func());
+  data.insert("mode".to_string(), math::mode_func());
+  data.insert("stddev".to_string(), math::stddev_func());
+  data.insert("sleep".to_string(), sleep_func());
+  data.insert("not".to_string(), not_func());
+  data.insert("truthy?".to_string(), truthy_func());
+  data.insert("falsy?".to_string(), falsy_func());
+  data.insert("=".to_string(), RispExp::Func(ensure_tonicity!(|a: &f64, b: &f64| math::numbers_equal(*a, *b))));
   data.insert(">".to_string(), RispExp::Func(ensure_tonicity!(|a, b| a > b)));
   data.insert(">=".to_string(), RispExp::Func(ensure_tonicity!(|a, b| a >= b)));
   data.insert("<".to_string(), RispExp::Func(ensure_tonicity!(|a, b| a < b)));
   data.insert("<=".to_string(), RispExp::Func(ensure_tonicity!(|a, b| a <= b)));
-  RispEnv {data, outer: None}
+  RispEnv::new(data, None)
 }
 
 /*
@@ -261,21 +576,95 @@ fn eval_if_args(arg_forms: &[RispExp], env: &mut RispEnv) -> Result<RispExp, Ris
     )
   )?;
   let test_eval = eval(test_form, env)?;
-  match test_eval {
-    RispExp::Bool(b) => {
-      let form_idx = if b { 1 } else { 2 };
-      let res_form = arg_forms.get(form_idx)
-        .ok_or(RispErr::Reason(
-          format!("expected form idx={}", form_idx)
-        ))?;
-      let res_eval = eval(res_form, env);
-      
-      res_eval
-    },
-    _ => Err(
-      RispErr::Reason(format!("unexpected test form='{}'", test_form.to_string()))
-    )
+  let form_idx = if is_truthy(&test_eval) { 1 } else { 2 };
+  let res_form = arg_forms.get(form_idx)
+    .ok_or(RispErr::Reason(
+      format!("expected form idx={}", form_idx)
+    ))?;
+  eval(res_form, env)
+}
+
+// false and nil are false, everything else is true
+fn is_truthy(exp: &RispExp) -> bool {
+  match exp {
+    RispExp::Bool(b) => *b,
+    RispExp::List(list) => !list.is_empty(),
+    _ => true,
+  }
+}
+
+fn not_func() -> RispExp {
+  RispExp::Func(
+    |args: &[RispExp]| -> Result<RispExp, RispErr> {
+      match args {
+        [x] => Ok(RispExp::Bool(!is_truthy(x))),
+        _ => Err(RispErr::Reason("not takes a single value".to_string()))
+      }
+    }
+  )
+}
+
+fn truthy_func() -> RispExp {
+  RispExp::Func(
+    |args: &[RispExp]| -> Result<RispExp, RispErr> {
+      match args {
+        [x] => Ok(RispExp::Bool(is_truthy(x))),
+        _ => Err(RispErr::Reason("truthy? takes a single value".to_string()))
+      }
+    }
+  )
+}
+
+fn falsy_func() -> RispExp {
+  RispExp::Func(
+    |args: &[RispExp]| -> Result<RispExp, RispErr> {
+      match args {
+        [x] => Ok(RispExp::Bool(!is_truthy(x))),
+        _ => Err(RispErr::Reason("falsy? takes a single value".to_string()))
+      }
+    }
+  )
+}
+
+// Stops at the first false value so the forms after it aren't evaluated
+fn eval_and_args(arg_forms: &[RispExp], env: &mut RispEnv) -> Result<RispExp, RispErr> {
+  let mut result = RispExp::Bool(true);
+  for form in arg_forms {
+    result = eval(form, env)?;
+    if !is_truthy(&result) {
+      return Ok(RispExp::Bool(false));
+    }
+  }
+  Ok(result)
+}
+
+// Stops at the first true value so the forms after it aren't evaluated
+fn eval_or_args(arg_forms: &[RispExp], env: &mut RispEnv) -> Result<RispExp, RispErr> {
+  for form in arg_forms {
+    let result = eval(form, env)?;
+    if is_truthy(&result) {
+      return Ok(result);
+    }
+  }
+  Ok(RispExp::Bool(false))
+}
+
+// Redefining a builtin is almost always a mistake, so def warns about
+// it, or with --strict refuses
+thread_local! {
+  static STRICT: Cell<bool> = const { Cell::new(false) };
+  static BUILTINS: HashSet<String> = default_env().data.borrow().keys().cloned().collect();
+}
+
+fn check_shadowing(name: &str) -> Result<(), RispErr> {
+  if !BUILTINS.with(|builtins| builtins.contains(name)) {
+    return Ok(());
+  }
+  if STRICT.with(|s| s.get()) {
+    return Err(RispErr::Reason(format!("cannot redefine builtin {}", name)));
   }
+  diagnostic(format!("warning: redefining builtin {}", name));
+  Ok(())
 }
 
 fn eval_def_args(arg_forms: &[RispExp], env: &mut RispEnv) -> Result<RispExp, RispErr> {
@@ -302,28 +691,46 @@ fn eval_def_args(arg_forms: &[RispExp], env: &mut RispEnv) -> Result<RispExp, Ri
       )
     )
   } 
+  if env.consts.borrow().contains(&first_str) {
+    return Err(RispErr::Reason(format!("cannot reassign constant {}", first_str)));
+  }
+  check_shadowing(&first_str)?;
   let second_eval = eval(second_form, env)?;
-  env.data.insert(first_str, second_eval);
+  env.data.borrow_mut().insert(first_str, second_eval);
   
   Ok(first_form.clone())
 }
 
+fn eval_defconst_args(arg_forms: &[RispExp], env: &mut RispEnv) -> Result<RispExp, RispErr> {
+  let name = eval_def_args(arg_forms, env)?;
+  if let RispExp::Symbol(s) = &name {
+    env.consts.borrow_mut().insert(s.clone());
+  }
+  Ok(name)
+}
+
 
-fn eval_lambda_args(arg_forms: &[RispExp]) -> Result<RispExp, RispErr> {
+// Only the current env is touched, a binding that lives in an outer
+// env (like a global seen from inside a lambda) is left alone
+fn eval_undef_args(arg_forms: &[RispExp], env: &mut RispEnv) -> Result<RispExp, RispErr> {
+  match arg_forms {
+    [RispExp::Symbol(k)] if env.consts.borrow().contains(k) => Err(RispErr::Reason(format!("cannot undef constant {}", k))),
+    [RispExp::Symbol(k)] => Ok(RispExp::Bool(env.data.borrow_mut().remove(k).is_some())),
+    _ => Err(RispErr::Reason("undef takes a single symbol".to_string()))
+  }
+}
+
+fn eval_lambda_args(arg_forms: &[RispExp], env: &RispEnv) -> Result<RispExp, RispErr> {
   let params_exp = arg_forms.first().ok_or(
     RispErr::Reason(
       "expected args form".to_string(),
     )
   )?;
-  let body_exp = arg_forms.get(1).ok_or(
-    RispErr::Reason(
-      "expected second form".to_string(),
-    )
-  )?;
-  if arg_forms.len() > 2 {
+  let body_exps = &arg_forms[1..];
+  if body_exps.is_empty() {
     return Err(
       RispErr::Reason(
-        "fn definition can only have two forms ".to_string(),
+        "expected body forms".to_string(),
       )
     )
   }
@@ -331,228 +738,1239 @@ fn eval_lambda_args(arg_forms: &[RispExp]) -> Result<RispExp, RispErr> {
   Ok(
     RispExp::Lambda(
       RispLambda {
-        body_exp: Rc::new(body_exp.clone()),
+        body_exps: Rc::new(body_exps.to_vec()),
         params_exp: Rc::new(params_exp.clone()),
+        env: env.clone(),
       }
     )
   )
 }
 
-fn eval_repeat_args(arg_forms: &[RispExp], env: &mut RispEnv) -> Result<RispExp, RispErr> {
-  let (func_form, rest) = arg_forms.split_first().ok_or(
+// (letfn ((name (params) body) ...) body ...) binds the functions in
+// a child env so they can call each other but don't leak out
+fn eval_letfn_args(arg_forms: &[RispExp], env: &mut RispEnv) -> Result<RispExp, RispErr> {
+  let (defs_form, body_forms) = arg_forms.split_first().ok_or(
     RispErr::Reason(
-      "expected function form".to_string(),
+      "expected function definitions".to_string(),
     )
   )?;
-  let lambda = eval(func_form, env)?;
-  let lambda = match lambda {
-    RispExp::Lambda(f) => f,
-    _ => return Err(RispErr::Reason("not a function".to_string()))
+  let defs = match defs_form {
+    RispExp::List(defs) => defs,
+    _ => return Err(RispErr::Reason("expected a list of function definitions".to_string()))
   };
-  let list_form = rest.first().ok_or(RispErr::Reason("expected list".to_string()))?;
-  let list_val =  eval(list_form, env)?;
-  match list_val {
-    RispExp::List(l) => {
-      let mut result_vec = Vec::new();
-      for risp_val in l {
-	let args = &[risp_val];
-	let new_env = &mut env_for_lambda(lambda.params_exp.clone(), args, env)?;
-        let result_val = eval(&lambda.body_exp, new_env)?;
-	result_vec.push(result_val);
-      }
-      Ok(RispExp::List(result_vec))
-    },
-    _ => Err(RispErr::Reason("not a list".to_string()))
+  let local_env = &mut RispEnv::new(HashMap::new(), Some(env));
+  for def in defs {
+    match def {
+      RispExp::List(def) => match def.split_first() {
+        Some((RispExp::Symbol(name), lambda_forms)) => {
+          let lambda = eval_lambda_args(lambda_forms, local_env)?;
+          local_env.data.borrow_mut().insert(name.clone(), lambda);
+        },
+        _ => return Err(RispErr::Reason("expected a function name".to_string()))
+      },
+      _ => return Err(RispErr::Reason("expected a function definition".to_string()))
+    }
+  }
+  if body_forms.is_empty() {
+    return Err(RispErr::Reason("expected body forms".to_string()));
   }
+  let mut result = RispExp::List(vec![]);
+  for form in body_forms {
+    result = eval(form, local_env)?;
+  }
+  Ok(result)
 }
 
-fn eval_built_in_form(
-  exp: &RispExp, arg_forms: &[RispExp], env: &mut RispEnv
-) -> Option<Result<RispExp, RispErr>> {
-  match exp {
-    RispExp::Symbol(s) => 
-      match s.as_ref() {
-        "if" => Some(eval_if_args(arg_forms, env)),
-        "def" => Some(eval_def_args(arg_forms, env)),
-        "fn" => Some(eval_lambda_args(arg_forms)),
-	"repeat" => Some(eval_repeat_args(arg_forms, env)),
-        _ => None,
-      }
-    ,
-    _ => None,
+fn eval_loop_test(test_form: &RispExp, env: &mut RispEnv) -> Result<bool, RispErr> {
+  check_deadline()?;
+  check_interrupt()?;
+  Ok(is_truthy(&eval(test_form, env)?))
+}
+
+// (do-while body ... test) runs the body at least once, then again
+// for as long as the test is true
+fn eval_do_while_args(arg_forms: &[RispExp], env: &mut RispEnv) -> Result<RispExp, RispErr> {
+  let (test_form, body_forms) = arg_forms.split_last().ok_or(
+    RispErr::Reason(
+      "expected test form".to_string(),
+    )
+  )?;
+  loop {
+    let mut result = RispExp::List(vec![]);
+    for form in body_forms {
+      result = eval(form, env)?;
+    }
+    if !eval_loop_test(test_form, env)? {
+      return Ok(result);
+    }
   }
 }
 
-fn env_get(k: &str, env: &RispEnv) -> Option<RispExp> {
-  if false {
-    for (key, value) in &env.data {
-      println!("env key {}: {}", key, value);
+// (until test body ...) runs the body until the test is true, which
+// may be never
+fn eval_until_args(arg_forms: &[RispExp], env: &mut RispEnv) -> Result<RispExp, RispErr> {
+  let (test_form, body_forms) = arg_forms.split_first().ok_or(
+    RispErr::Reason(
+      "expected test form".to_string(),
+    )
+  )?;
+  let mut result = RispExp::List(vec![]);
+  while !eval_loop_test(test_form, env)? {
+    for form in body_forms {
+      result = eval(form, env)?;
     }
   }
+  Ok(result)
+}
 
-  // Self quoted symbols just resolve to themselves
-  if k.starts_with(":") {
-    return Some(RispExp::Symbol(k.to_string()));
+// (binding (var value ...) body ...) rebinds the vars in the env that
+// defines them, or the current env if none does, while the body runs,
+// so functions the body calls see the new values too. The old
+// bindings are put back afterwards, even if the body fails.
+fn eval_binding_args(arg_forms: &[RispExp], env: &mut RispEnv) -> Result<RispExp, RispErr> {
+  let (bindings_form, body_forms) = arg_forms.split_first().ok_or(
+    RispErr::Reason(
+      "expected bindings form".to_string(),
+    )
+  )?;
+  let bindings = match bindings_form {
+    RispExp::List(bindings) if bindings.len() % 2 == 0 => bindings,
+    _ => return Err(RispErr::Reason("expected a list of vars and values".to_string()))
+  };
+  // The value goes in whichever env defines the name, so that's the
+  // env whose constants count
+  let mut new_values = Vec::new();
+  for pair in bindings.chunks(2) {
+    let name = match &pair[0] {
+      RispExp::Symbol(name) => name.clone(),
+      _ => return Err(RispErr::Reason("expected a symbol to bind".to_string()))
+    };
+    let defining_env = env_defining(&name, env).unwrap_or_else(|| env.clone());
+    if defining_env.consts.borrow().contains(&name) {
+      return Err(RispErr::Reason(format!("cannot rebind constant {}", name)));
+    }
+    new_values.push((defining_env, name, eval(&pair[1], env)?));
+  }
+  let mut saved = Vec::new();
+  for (defining_env, name, value) in new_values {
+    let old = defining_env.data.borrow_mut().insert(name.clone(), value);
+    saved.push((defining_env, name, old));
   }
 
-  match env.data.get(k) {
-    Some(exp) => Some(exp.clone()),
-    None => {
-      match &env.outer {
-        Some(outer_env) => env_get(k, &outer_env),
-        None => None
-      }
+  let mut result = Ok(RispExp::List(vec![]));
+  for form in body_forms {
+    result = eval(form, env);
+    if result.is_err() {
+      break;
     }
   }
-}
 
-fn parse_list_of_symbol_strings(form: Rc<RispExp>) -> Result<Vec<String>, RispErr> {
-  let list = match form.as_ref() {
-    RispExp::List(s) => Ok(s.clone()),
-    _ => Err(RispErr::Reason(
-      "expected args form to be a list".to_string(),
-    ))
-  }?;
-  list
-    .iter()
-    .map(
-      |x| {
-        match x {
-          RispExp::Symbol(s) => Ok(s.clone()),
-          _ => Err(RispErr::Reason(
-            "expected symbols in the argument list".to_string(),
-          ))
-        }   
-      }
-    ).collect()
+  for (defining_env, name, old) in saved.into_iter().rev() {
+    let mut data = defining_env.data.borrow_mut();
+    match old {
+      Some(value) => data.insert(name, value),
+      None => data.remove(&name),
+    };
+  }
+  result
 }
 
-fn env_for_lambda<'a>(
-  params: Rc<RispExp>, 
-  arg_forms: &[RispExp],
-  outer_env: &'a mut RispEnv,
-) -> Result<RispEnv<'a>, RispErr> {
-  let ks = parse_list_of_symbol_strings(params)?;
-  if ks.len() != arg_forms.len() {
-    return Err(
-      RispErr::Reason(
-        format!("expected {} arguments, got {}", ks.len(), arg_forms.len())
-      )
-    );
+// (begin form ...), also called do, evaluates the forms in the
+// current env and returns the value of the last
+fn eval_begin_args(arg_forms: &[RispExp], env: &mut RispEnv) -> Result<RispExp, RispErr> {
+  if arg_forms.is_empty() {
+    return Err(RispErr::Reason("begin expects at least one form".to_string()));
   }
-  let vs = eval_forms(arg_forms, outer_env)?;
-  let mut data: HashMap<String, RispExp> = HashMap::new();
-  for (k, v) in ks.iter().zip(vs.iter()) {
-    data.insert(k.clone(), v.clone());
+  let mut result = RispExp::List(vec![]);
+  for form in arg_forms {
+    result = eval(form, env)?;
   }
-  Ok(
-    RispEnv {
-      data,
-      outer: Some(outer_env),
-    }
-  )
+  Ok(result)
 }
 
-fn eval_forms(arg_forms: &[RispExp], env: &mut RispEnv) -> Result<Vec<RispExp>, RispErr> {
-  arg_forms
-    .iter()
-    .map(|x| eval(x, env))
-    .collect()
+fn eval_quote_args(arg_forms: &[RispExp]) -> Result<RispExp, RispErr> {
+  match arg_forms {
+    [form] => Ok(form.clone()),
+    _ => Err(RispErr::Reason("quote takes exactly one form".to_string()))
+  }
 }
 
-fn eval(exp: &RispExp, env: &mut RispEnv) -> Result<RispExp, RispErr> {
+// The form of (unquote x) or (unquote-splicing x) if exp is one
+fn unquoted<'a>(exp: &'a RispExp, name: &str) -> Option<&'a RispExp> {
   match exp {
-    RispExp::Symbol(k) =>
-      env_get(k, env)
-      .ok_or(
-        RispErr::Reason(
-          format!("unexpected symbol k='{}'", k)
-        )
-      ),
-    RispExp::Str(_a) => Ok(exp.clone()),
-    RispExp::Bool(_a) => Ok(exp.clone()),
-    RispExp::Number(_a) => Ok(exp.clone()),
-
-    RispExp::List(list) => {
-      let first_form = list
-        .first()
-        .ok_or(RispErr::Reason("expected a non-empty list".to_string()))?;
-      let arg_forms = &list[1..];
-      match eval_built_in_form(first_form, arg_forms, env) {
-        Some(res) => res,
-        None => {
-          let first_eval = eval(first_form, env)?;
-          match first_eval {
-            RispExp::Func(f) => {
-              f(&eval_forms(arg_forms, env)?)
-            },
-            RispExp::Lambda(lambda) => {
-              let new_env = &mut env_for_lambda(lambda.params_exp, arg_forms, env)?;
-              eval(&lambda.body_exp, new_env)
-            },
-            _ => Err(
-              RispErr::Reason("first form must be a function".to_string())
-            ),
-          }
-        }
-      }
+    RispExp::List(list) => match &list[..] {
+      [RispExp::Symbol(s), form] if s == name => Some(form),
+      _ => None,
     },
-    RispExp::Func(_) => Err(RispErr::Reason("unexpected form".to_string())),
-    RispExp::Lambda(_) => Err(RispErr::Reason("unexpected form".to_string())),
-    RispExp::Json(_) => Ok(exp.clone()),
+    _ => None,
   }
 }
 
-/*
-  Repl
-*/
-
-fn parse_eval(expr: String, env: &mut RispEnv) -> Option<Result<RispExp, RispErr>> {
-  let tokens = &tokenize(expr);
-  if tokens.len() < 1 {
-    return None;
+// Copies the form, evaluating anything that's unquoted and splicing
+// the lists from unquote-splicing into the list around them
+fn quasiquote(form: &RispExp, env: &mut RispEnv) -> Result<RispExp, RispErr> {
+  if let Some(unquoted_form) = unquoted(form, "unquote") {
+    return eval(unquoted_form, env);
   }
-
-  let (parsed_exp, _) = parse(tokens).unwrap();
-  match eval(&parsed_exp, env) {
-    Ok(evaled_exp) => Some(Ok(evaled_exp)),
-    Err(e) => Some(Err(e))
+  if unquoted(form, "unquote-splicing").is_some() {
+    return Err(RispErr::Reason("unquote-splicing outside a list".to_string()));
+  }
+  let list = match form {
+    RispExp::List(list) => list,
+    _ => return Ok(form.clone()),
+  };
+  let mut result = Vec::new();
+  for item in list {
+    match unquoted(item, "unquote-splicing") {
+      Some(spliced_form) => match eval(spliced_form, env)? {
+        RispExp::List(spliced) => result.extend(spliced),
+        other => return Err(RispErr::Reason(format!("can only splice a list: {}", other))),
+      },
+      None => result.push(quasiquote(item, env)?),
+    }
   }
+  Ok(RispExp::List(result))
 }
 
-#[derive(Debug)]
-enum RispIOErr {
-  Reason(String),
+fn eval_quasiquote_args(arg_forms: &[RispExp], env: &mut RispEnv) -> Result<RispExp, RispErr> {
+  match arg_forms {
+    [form] => quasiquote(form, env),
+    _ => Err(RispErr::Reason("quasiquote takes exactly one form".to_string()))
+  }
 }
-  
-fn slurp_expr() -> Result<String, RispIOErr> {
-  let mut expr = String::new();
-  
-  let red = io::stdin().read_line(&mut expr)
-    .expect("Failed to read line");
 
-  if red == 0 {
-    println!(); // newline to clear up the terminal
-    return Err(RispIOErr::Reason("EOF".to_string()));
+// The symbol isn't evaluated, nil when there's no doc for it
+fn eval_doc_args(arg_forms: &[RispExp]) -> Result<RispExp, RispErr> {
+  match arg_forms {
+    [RispExp::Symbol(k)] => Ok(match builtin_doc(k) {
+      Some(doc) => RispExp::Str(doc.to_string()),
+      None => RispExp::List(vec![]),
+    }),
+    _ => Err(RispErr::Reason("expected a symbol".to_string()))
   }
-
-  Ok(expr)
+}
+
+fn eval_defined_args(arg_forms: &[RispExp], env: &mut RispEnv) -> Result<RispExp, RispErr> {
+  let form = arg_forms.first().ok_or(
+    RispErr::Reason(
+      "expected a symbol form".to_string(),
+    )
+  )?;
+  match eval(form, env)? {
+    RispExp::Symbol(k) => Ok(RispExp::Bool(env_get(&k, env).is_some())),
+    _ => Err(RispErr::Reason("expected a quoted symbol".to_string()))
+  }
+}
+
+// Warnings and progress, benchmark, step and trace lines go to stderr,
+// unless a test is capturing them to check what was written
+thread_local! {
+  static CAPTURED: RefCell<Option<Vec<String>>> = const { RefCell::new(None) };
+}
+
+fn diagnostic(line: String) {
+  CAPTURED.with(|captured| match captured.borrow_mut().as_mut() {
+    Some(lines) => lines.push(line),
+    None => eprintln!("{}", line),
+  })
+}
+
+// When *progress* is a number n, long running loops over a list write
+// how far they've got to stderr every n items
+fn progress_interval(env: &RispEnv) -> Option<usize> {
+  match env_get("*progress*", env) {
+    Some(RispExp::Number(n)) if n >= 1.0 => Some(n as usize),
+    _ => None,
+  }
+}
+
+fn report_progress(done: usize, total: usize, interval: Option<usize>) {
+  if let Some(interval) = interval {
+    if done.is_multiple_of(interval) || done == total {
+      diagnostic(format!("{}/{}", done, total));
+    }
+  }
+}
+
+fn eval_repeat_args(arg_forms: &[RispExp], env: &mut RispEnv) -> Result<RispExp, RispErr> {
+  let (func_form, rest) = arg_forms.split_first().ok_or(
+    RispErr::Reason(
+      "expected function form".to_string(),
+    )
+  )?;
+  let lambda = eval(func_form, env)?;
+  // a lambda of two parameters is also given the index of the item
+  let with_index = match &lambda {
+    RispExp::Lambda(l) => matches!(l.params_exp.as_ref(), RispExp::List(params) if params.len() == 2),
+    _ => return Err(RispErr::Reason(format!("not a function: {}", lambda))),
+  };
+  let list_form = rest.first().ok_or(RispErr::Reason("expected list".to_string()))?;
+  let list_val =  eval(list_form, env)?;
+  match list_val {
+    RispExp::List(l) => {
+      let interval = progress_interval(env);
+      let total = l.len();
+      let mut result_vec = Vec::new();
+      for (i, risp_val) in l.into_iter().enumerate() {
+        let result_val = if with_index {
+          apply_function(&lambda, &[risp_val, RispExp::Number(i as f64)])?
+        } else {
+          apply_function(&lambda, &[risp_val])?
+        };
+	result_vec.push(result_val);
+        report_progress(i + 1, total, interval);
+      }
+      Ok(RispExp::List(result_vec))
+    },
+    other => Err(RispErr::Reason(format!("not a list: {}", other)))
+  }
+}
+
+// map is also a builtin so it can be passed around, but called by name
+// it can see *progress* and reports how far it's got like repeat
+fn eval_map_args(arg_forms: &[RispExp], env: &mut RispEnv) -> Result<RispExp, RispErr> {
+  let args = eval_forms(arg_forms, env)?;
+  let interval = progress_interval(env);
+  lists::map_items(&args, |done, total| report_progress(done, total, interval))
+}
+
+// (apply-times n f x) is f applied n times starting from x, named so
+// as not to be confused with repeat which maps over a list
+fn eval_apply_times_args(arg_forms: &[RispExp], env: &mut RispEnv) -> Result<RispExp, RispErr> {
+  let args = eval_forms(arg_forms, env)?;
+  let (n, f, initial) = match &args[..] {
+    [RispExp::Number(n), f, initial] => (*n, f, initial),
+    [n, _, _] => return Err(RispErr::Reason(format!("count must be a number: {}", n))),
+    _ => return Err(RispErr::Reason("expected a count, a function and a value".to_string()))
+  };
+  if n < 0.0 || n.fract() != 0.0 {
+    return Err(RispErr::Reason(format!("count must be a non-negative integer, got {}", n)));
+  }
+  let mut result = initial.clone();
+  for _ in 0..n as u64 {
+    result = apply_function(f, &[result])?;
+  }
+  Ok(result)
+}
+
+// (reduce f initial list) threads an accumulator through f, an empty
+// list gives back initial
+// The function, initial value and list reduce and reductions take
+fn reduce_args<'a>(args: &'a [RispExp], name: &str) -> Result<(&'a RispExp, &'a RispExp, &'a Vec<RispExp>), RispErr> {
+  let (f, initial, list) = match args {
+    [f, initial, RispExp::List(list)] => (f, initial, list),
+    [_, _, list] => return Err(RispErr::Reason(format!("not a list: {}", list))),
+    _ => return Err(RispErr::Reason("expected a function, an initial value and a list".to_string()))
+  };
+  match f {
+    RispExp::Lambda(l) if !matches!(l.params_exp.as_ref(), RispExp::List(params) if params.len() == 2) => {
+      Err(RispErr::Reason(format!("{} needs a function of two arguments", name)))
+    },
+    RispExp::Func(_) | RispExp::Lambda(_) => Ok((f, initial, list)),
+    _ => Err(RispErr::Reason(format!("not a function: {}", f))),
+  }
+}
+
+fn eval_reduce_args(arg_forms: &[RispExp], env: &mut RispEnv) -> Result<RispExp, RispErr> {
+  let args = eval_forms(arg_forms, env)?;
+  let (f, initial, list) = reduce_args(&args, "reduce")?;
+  let mut acc = initial.clone();
+  for x in list {
+    acc = apply_function(f, &[acc, x.clone()])?;
+  }
+  Ok(acc)
+}
+
+// Like reduce but keeps every value the accumulator takes, starting
+// with the initial value
+fn eval_reductions_args(arg_forms: &[RispExp], env: &mut RispEnv) -> Result<RispExp, RispErr> {
+  let args = eval_forms(arg_forms, env)?;
+  let (f, initial, list) = reduce_args(&args, "reductions")?;
+  let mut acc = initial.clone();
+  let mut steps = vec![acc.clone()];
+  for x in list {
+    acc = apply_function(f, &[acc, x.clone()])?;
+    steps.push(acc.clone());
+  }
+  Ok(RispExp::List(steps))
+}
+
+// Splits a list into runs of neighbouring elements with equal keys
+fn eval_partition_by_args(arg_forms: &[RispExp], env: &mut RispEnv) -> Result<RispExp, RispErr> {
+  let args = eval_forms(arg_forms, env)?;
+  let (f, list) = match &args[..] {
+    [f, RispExp::List(list)] => (f, list),
+    [_, list] => return Err(RispErr::Reason(format!("not a list: {}", list))),
+    _ => return Err(RispErr::Reason("expected a function and a list".to_string()))
+  };
+  let mut runs: Vec<RispExp> = Vec::new();
+  let mut run: Vec<RispExp> = Vec::new();
+  let mut run_key: Option<RispExp> = None;
+  for x in list {
+    let key = apply_function(f, std::slice::from_ref(x))?;
+    if let Some(k) = &run_key {
+      if !lists::values_equal(k, &key) {
+        runs.push(RispExp::List(std::mem::take(&mut run)));
+      }
+    }
+    run_key = Some(key);
+    run.push(x.clone());
+  }
+  if !run.is_empty() {
+    runs.push(RispExp::List(run));
+  }
+  Ok(RispExp::List(runs))
+}
+
+// A missing key isn't an error, the function is applied to nil and
+// the new pair goes on the end of the list
+fn eval_assoc_update_args(arg_forms: &[RispExp], env: &mut RispEnv) -> Result<RispExp, RispErr> {
+  let args = eval_forms(arg_forms, env)?;
+  let (alist, key, f) = match &args[..] {
+    [RispExp::List(alist), key, f] => (alist, key, f),
+    [alist, _, _] => return Err(RispErr::Reason(format!("not a list: {}", alist))),
+    _ => return Err(RispErr::Reason("expected a list, a key and a function".to_string()))
+  };
+  let mut result = alist.clone();
+  match lists::alist_position(key, &result) {
+    Some(i) => {
+      let old = match &result[i] {
+        RispExp::List(kv) => kv.get(1).cloned().unwrap_or(RispExp::List(vec![])),
+        _ => RispExp::List(vec![])
+      };
+      let new = apply_function(f, &[old])?;
+      result[i] = RispExp::List(vec![key.clone(), new]);
+    },
+    None => {
+      let new = apply_function(f, &[RispExp::List(vec![])])?;
+      result.push(RispExp::List(vec![key.clone(), new]));
+    }
+  }
+  Ok(RispExp::List(result))
+}
+
+// Keys only in one list pass through, keys in both get f of the two values
+fn eval_merge_with_args(arg_forms: &[RispExp], env: &mut RispEnv) -> Result<RispExp, RispErr> {
+  let args = eval_forms(arg_forms, env)?;
+  let (f, first, second) = match &args[..] {
+    [f, RispExp::List(first), RispExp::List(second)] => (f, first, second),
+    [_, RispExp::List(_), other] | [_, other, _] => return Err(RispErr::Reason(format!("not an assoc list: {}", other))),
+    _ => return Err(RispErr::Reason("expected a function and two assoc lists".to_string()))
+  };
+  let mut result = first.clone();
+  for pair in second {
+    let (key, value) = match pair {
+      RispExp::List(kv) if kv.len() == 2 => (&kv[0], &kv[1]),
+      _ => return Err(RispErr::Reason(format!("not an assoc list: {}", pair)))
+    };
+    match lists::alist_position(key, &result) {
+      Some(i) => {
+        let old = match &result[i] {
+          RispExp::List(kv) => kv.get(1).cloned().unwrap_or(RispExp::List(vec![])),
+          _ => RispExp::List(vec![])
+        };
+        let new = apply_function(f, &[old, value.clone()])?;
+        result[i] = RispExp::List(vec![key.clone(), new]);
+      },
+      None => result.push(pair.clone())
+    }
+  }
+  Ok(RispExp::List(result))
+}
+
+// A with-timeout form runs on a thread of its own, which checks this
+// deadline each time it applies a form. That way a form that's given
+// up on stops itself rather than carrying on in the background.
+thread_local! {
+  static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+fn check_deadline() -> Result<(), RispErr> {
+  match DEADLINE.with(|d| d.get()) {
+    Some(deadline) if Instant::now() > deadline => Err(RispErr::Reason("timed out".to_string())),
+    _ => Ok(()),
+  }
+}
+
+// Set by the Ctrl-C handler, eval notices it at the same points it
+// checks the deadline and gives up back to the prompt
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static EVALUATING: AtomicBool = AtomicBool::new(false);
+
+fn check_interrupt() -> Result<(), RispErr> {
+  take_interrupt(&INTERRUPTED)
+}
+
+// Clears the flag, so one Ctrl-C interrupts one evaluation
+fn take_interrupt(flag: &AtomicBool) -> Result<(), RispErr> {
+  if flag.swap(false, Ordering::SeqCst) {
+    return Err(RispErr::Reason("interrupted".to_string()));
+  }
+  Ok(())
+}
+
+// (benchmark n form) evaluates form n times, writes how long it took
+// to stderr and returns the last value
+fn eval_benchmark_args(arg_forms: &[RispExp], env: &mut RispEnv) -> Result<RispExp, RispErr> {
+  let (n, body_form) = match arg_forms {
+    [n_form, body_form] => match eval(n_form, env)? {
+      RispExp::Number(n) if n >= 1.0 && n.fract() == 0.0 => (n as u64, body_form),
+      other => return Err(RispErr::Reason(format!("iterations must be a positive integer: {}", other)))
+    },
+    _ => return Err(RispErr::Reason("expected an iteration count and a form".to_string()))
+  };
+  let start = Instant::now();
+  let mut result = RispExp::List(vec![]);
+  for _ in 0..n {
+    result = eval(body_form, env)?;
+  }
+  let elapsed = start.elapsed();
+  diagnostic(format!(
+    "{} iterations in {:?}, {:?} each, {:.0} per second",
+    n,
+    elapsed,
+    elapsed.div_f64(n as f64),
+    n as f64 / elapsed.as_secs_f64()
+  ));
+  Ok(result)
+}
+
+// While stepping every reduction eval makes, a call's arguments being
+// evaluated or a form being evaluated to its value, is written to stderr
+thread_local! {
+  static STEPPING: Cell<bool> = const { Cell::new(false) };
+}
+
+fn step_reduced(form: &RispExp, reduced: &RispExp) {
+  if STEPPING.with(|s| s.get()) {
+    diagnostic(format!("{} => {}", form, reduced));
+  }
+}
+
+fn eval_step_args(arg_forms: &[RispExp], env: &mut RispEnv) -> Result<RispExp, RispErr> {
+  let form = match arg_forms {
+    [form] => form,
+    _ => return Err(RispErr::Reason("step takes exactly one form".to_string()))
+  };
+  let outer = STEPPING.with(|s| s.replace(true));
+  let result = eval(form, env);
+  STEPPING.with(|s| s.set(outer));
+  result
+}
+
+// While tracing this is the depth of the call being made, calls and
+// their results are written to stderr indented by it
+thread_local! {
+  static TRACE_DEPTH: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+fn traced_apply(name: &RispExp, f: &RispExp, args: &[RispExp]) -> Result<RispExp, RispErr> {
+  let name = match name {
+    RispExp::Symbol(s) => Some(s.as_str()),
+    _ => None,
+  };
+  let depth = match TRACE_DEPTH.with(|d| d.get()) {
+    Some(depth) => depth,
+    None => return apply_named_function(f, args, name),
+  };
+  let indent = "  ".repeat(depth);
+  let arg_strs: Vec<String> = args.iter().map(|x| x.to_string()).collect();
+  diagnostic(format!("{}({} {})", indent, name.unwrap_or("fn"), arg_strs.join(" ")));
+  TRACE_DEPTH.with(|d| d.set(Some(depth + 1)));
+  let result = apply_named_function(f, args, name);
+  TRACE_DEPTH.with(|d| d.set(Some(depth)));
+  match &result {
+    Ok(res) => diagnostic(format!("{}=> {}", indent, res)),
+    Err(RispErr::Reason(msg)) => diagnostic(format!("{}!! {}", indent, msg)),
+  }
+  result
+}
+
+fn eval_trace_args(arg_forms: &[RispExp], env: &mut RispEnv) -> Result<RispExp, RispErr> {
+  let form = match arg_forms {
+    [form] => form,
+    _ => return Err(RispErr::Reason("trace takes exactly one form".to_string()))
+  };
+  let outer_depth = TRACE_DEPTH.with(|d| d.get());
+  TRACE_DEPTH.with(|d| d.set(Some(outer_depth.unwrap_or(0))));
+  let result = eval(form, env);
+  TRACE_DEPTH.with(|d| d.set(outer_depth));
+  result
+}
+
+fn eval_with_timeout_args(arg_forms: &[RispExp], env: &mut RispEnv) -> Result<RispExp, RispErr> {
+  let (ms_form, rest) = arg_forms.split_first().ok_or(
+    RispErr::Reason(
+      "expected milliseconds form".to_string(),
+    )
+  )?;
+  let ms = match eval(ms_form, env)? {
+    RispExp::Number(ms) if ms >= 0.0 => ms,
+    other => return Err(RispErr::Reason(format!("timeout must be a positive number: {}", other)))
+  };
+  let body_form = rest.first().ok_or(RispErr::Reason("expected a form to evaluate".to_string()))?;
+
+  // an enclosing with-timeout that expires sooner still wins
+  let deadline = Instant::now() + Duration::from_millis(ms as u64);
+  let deadline = match DEADLINE.with(|d| d.get()) {
+    Some(outer) if outer < deadline => outer,
+    _ => deadline,
+  };
+
+  // The worker gets a copy of the form and the env, so defs it makes
+  // aren't seen here. The result is detached again before it's sent,
+  // so nothing the worker still holds is shared with this thread.
+  let work = detach::detach(body_form, Some(env));
+  let strict = STRICT.with(|s| s.get());
+  let stepping = STEPPING.with(|s| s.get());
+  let trace_depth = TRACE_DEPTH.with(|d| d.get());
+  let (sender, receiver) = mpsc::channel();
+  thread::spawn(move || {
+    STRICT.with(|s| s.set(strict));
+    STEPPING.with(|s| s.set(stepping));
+    TRACE_DEPTH.with(|d| d.set(trace_depth));
+    DEADLINE.with(|d| d.set(Some(deadline)));
+    let (form, env) = detach::attach(work);
+    let result = eval(&form, &mut env.unwrap());
+    // the caller may have stopped waiting, then there's no one to tell
+    let _ = sender.send(result.map(|value| detach::detach(&value, None)));
+  });
+
+  match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+    Ok(result) => result.map(|value| detach::attach(value).0),
+    Err(mpsc::RecvTimeoutError::Timeout) => Err(RispErr::Reason("timed out".to_string())),
+    Err(mpsc::RecvTimeoutError::Disconnected) => Err(RispErr::Reason("internal error: with-timeout worker stopped".to_string())),
+  }
+}
+
+// Blocks without evaluating anything, like a slow call would
+fn sleep_func() -> RispExp {
+  RispExp::Func(
+    |args: &[RispExp]| -> Result<RispExp, RispErr> {
+      match args {
+        [RispExp::Number(ms)] if *ms >= 0.0 => {
+          thread::sleep(Duration::from_millis(*ms as u64));
+          Ok(RispExp::List(vec![]))
+        },
+        _ => Err(RispErr::Reason("sleep takes a number of milliseconds".to_string()))
+      }
+    }
+  )
+}
+
+// Up to half the delay again at random, so clients that failed
+// together don't all retry together
+fn jitter(delay: Duration) -> Duration {
+  let random = RandomState::new().build_hasher().finish();
+  delay.mul_f64((random % 1000) as f64 / 2000.0)
+}
+
+fn eval_delay(form: Option<&RispExp>, default_ms: u64, env: &mut RispEnv) -> Result<Duration, RispErr> {
+  let form = match form {
+    Some(form) => form,
+    None => return Ok(Duration::from_millis(default_ms)),
+  };
+  match eval(form, env)? {
+    RispExp::Number(ms) if ms >= 0.0 => Ok(Duration::from_millis(ms as u64)),
+    other => Err(RispErr::Reason(format!("delay must be a positive number: {}", other)))
+  }
+}
+
+// (with-retry-backoff attempts form [base-ms [cap-ms]]) evaluates form
+// until it succeeds, sleeping between tries for the base delay doubled
+// each time, never more than the cap, plus jitter
+fn eval_with_retry_backoff_args(arg_forms: &[RispExp], env: &mut RispEnv) -> Result<RispExp, RispErr> {
+  let (attempts_form, rest) = arg_forms.split_first().ok_or(
+    RispErr::Reason(
+      "expected attempts form".to_string(),
+    )
+  )?;
+  let (body_form, rest) = rest.split_first().ok_or(RispErr::Reason("expected a form to evaluate".to_string()))?;
+  let attempts = match eval(attempts_form, env)? {
+    RispExp::Number(n) if n >= 1.0 => n as u32,
+    other => return Err(RispErr::Reason(format!("attempts must be at least 1: {}", other)))
+  };
+  let base = eval_delay(rest.first(), 100, env)?;
+  let cap = eval_delay(rest.get(1), 10000, env)?;
+
+  let mut delay = base;
+  let mut attempt = 1;
+  loop {
+    match eval(body_form, env) {
+      Err(RispErr::Reason(reason)) if attempt < attempts && reason != "interrupted" && reason != "timed out" => {
+        std::thread::sleep(delay + jitter(delay));
+        delay = (delay * 2).min(cap);
+        attempt += 1;
+      },
+      result => return result,
+    }
+  }
+}
+
+fn eval_built_in_form(
+  exp: &RispExp, arg_forms: &[RispExp], env: &mut RispEnv
+) -> Option<Result<RispExp, RispErr>> {
+  match exp {
+    RispExp::Symbol(s) => 
+      match s.as_ref() {
+        "if" => Some(eval_if_args(arg_forms, env)),
+        "and" => Some(eval_and_args(arg_forms, env)),
+        "or" => Some(eval_or_args(arg_forms, env)),
+        "do-while" => Some(eval_do_while_args(arg_forms, env)),
+        "until" => Some(eval_until_args(arg_forms, env)),
+        "def" => Some(eval_def_args(arg_forms, env)),
+        "defconst" => Some(eval_defconst_args(arg_forms, env)),
+        "undef" => Some(eval_undef_args(arg_forms, env)),
+        "fn" => Some(eval_lambda_args(arg_forms, env)),
+        "letfn" => Some(eval_letfn_args(arg_forms, env)),
+        "binding" => Some(eval_binding_args(arg_forms, env)),
+        "begin" | "do" => Some(eval_begin_args(arg_forms, env)),
+        "quote" => Some(eval_quote_args(arg_forms)),
+        "quasiquote" => Some(eval_quasiquote_args(arg_forms, env)),
+        "unquote" | "unquote-splicing" => Some(Err(RispErr::Reason(format!("{} outside quasiquote", s)))),
+        "defined?" => Some(eval_defined_args(arg_forms, env)),
+        "doc" => Some(eval_doc_args(arg_forms)),
+        "bindings" => Some(eval_bindings_args(arg_forms, env)),
+	"repeat" => Some(eval_repeat_args(arg_forms, env)),
+        "map" => Some(eval_map_args(arg_forms, env)),
+        "apply-times" => Some(eval_apply_times_args(arg_forms, env)),
+        "partition-by" => Some(eval_partition_by_args(arg_forms, env)),
+        "reduce" => Some(eval_reduce_args(arg_forms, env)),
+        "reductions" => Some(eval_reductions_args(arg_forms, env)),
+        "assoc-update" | "update-in" => Some(eval_assoc_update_args(arg_forms, env)),
+        "merge-with" => Some(eval_merge_with_args(arg_forms, env)),
+        "with-timeout" => Some(eval_with_timeout_args(arg_forms, env)),
+        "with-retry-backoff" => Some(eval_with_retry_backoff_args(arg_forms, env)),
+        "trace" => Some(eval_trace_args(arg_forms, env)),
+        "step" => Some(eval_step_args(arg_forms, env)),
+        "benchmark" => Some(eval_benchmark_args(arg_forms, env)),
+        _ => None,
+      }
+    ,
+    _ => None,
+  }
+}
+
+fn env_get(k: &str, env: &RispEnv) -> Option<RispExp> {
+  if false {
+    for (key, value) in env.data.borrow().iter() {
+      println!("env key {}: {}", key, value);
+    }
+  }
+
+  // Self quoted symbols just resolve to themselves
+  if k.starts_with(":") {
+    return Some(RispExp::Symbol(k.to_string()));
+  }
+
+  let found = env.data.borrow().get(k).cloned();
+  match found {
+    Some(exp) => Some(exp),
+    None => {
+      match &env.outer {
+        Some(outer_env) => env_get(k, outer_env),
+        None => None
+      }
+    }
+  }
+}
+
+// The innermost env, starting from env, that has a binding for k
+fn env_defining(k: &str, env: &RispEnv) -> Option<RispEnv> {
+  if env.data.borrow().contains_key(k) {
+    return Some(env.clone());
+  }
+  env.outer.as_ref().and_then(|outer_env| env_defining(k, outer_env))
+}
+
+// Every binding visible from env, inner envs shadowing outer ones
+fn env_bindings(env: &RispEnv) -> HashMap<String, RispExp> {
+  let mut bindings = match &env.outer {
+    Some(outer_env) => env_bindings(outer_env),
+    None => HashMap::new()
+  };
+  for (k, v) in env.data.borrow().iter() {
+    bindings.insert(k.clone(), v.clone());
+  }
+  bindings
+}
+
+fn eval_bindings_args(arg_forms: &[RispExp], env: &mut RispEnv) -> Result<RispExp, RispErr> {
+  if !arg_forms.is_empty() {
+    return Err(RispErr::Reason("bindings takes no arguments".to_string()));
+  }
+  let mut bindings: Vec<(String, RispExp)> = env_bindings(env).into_iter().collect();
+  bindings.sort_by(|a, b| a.0.cmp(&b.0));
+  Ok(RispExp::List(
+    bindings
+      .into_iter()
+      .map(|(k, v)| RispExp::List(vec![RispExp::Symbol(k), v]))
+      .collect()
+  ))
+}
+
+fn parse_list_of_symbol_strings(form: Rc<RispExp>) -> Result<Vec<String>, RispErr> {
+  let list = match form.as_ref() {
+    RispExp::List(s) => Ok(s.clone()),
+    _ => Err(RispErr::Reason(
+      "expected args form to be a list".to_string(),
+    ))
+  }?;
+  list
+    .iter()
+    .map(
+      |x| {
+        match x {
+          RispExp::Symbol(s) => Ok(s.clone()),
+          _ => Err(RispErr::Reason(
+            "expected symbols in the argument list".to_string(),
+          ))
+        }   
+      }
+    ).collect()
+}
+
+// name is what the lambda was called as, if anything, for the errors
+fn env_for_lambda(
+  params: Rc<RispExp>,
+  vs: &[RispExp],
+  outer_env: &RispEnv,
+  name: Option<&str>,
+) -> Result<RispEnv, RispErr> {
+  let ks = parse_list_of_symbol_strings(params)?;
+  if ks.len() != vs.len() {
+    let noun = if ks.len() == 1 { "argument" } else { "arguments" };
+    let msg = format!("expected {} {}, got {}", ks.len(), noun, vs.len());
+    return Err(
+      RispErr::Reason(
+        match name {
+          Some(name) => format!("{}: {}", name, msg),
+          None => msg,
+        }
+      )
+    );
+  }
+  let mut data: HashMap<String, RispExp> = HashMap::new();
+  for (k, v) in ks.iter().zip(vs.iter()) {
+    data.insert(k.clone(), v.clone());
+  }
+  Ok(RispEnv::new(data, Some(outer_env)))
+}
+
+// Call a function value on already evaluated arguments, a lambda's
+// body runs in a child of the env it was made in
+// A panic in a builtin is a bug, but it shouldn't take the REPL down
+// with it, so it becomes an error like any other
+fn apply_builtin(f: fn(&[RispExp]) -> Result<RispExp, RispErr>, args: &[RispExp]) -> Result<RispExp, RispErr> {
+  match panic::catch_unwind(AssertUnwindSafe(|| f(args))) {
+    Ok(result) => result,
+    Err(payload) => {
+      let msg = match payload.downcast_ref::<&str>() {
+        Some(msg) => msg.to_string(),
+        None => payload.downcast_ref::<String>().cloned().unwrap_or_else(|| "unknown panic".to_string()),
+      };
+      Err(RispErr::Reason(format!("internal error: {}", msg)))
+    }
+  }
+}
+
+fn apply_function(f: &RispExp, args: &[RispExp]) -> Result<RispExp, RispErr> {
+  apply_named_function(f, args, None)
+}
+
+fn apply_named_function(f: &RispExp, args: &[RispExp], name: Option<&str>) -> Result<RispExp, RispErr> {
+  match f {
+    RispExp::Func(f) => apply_builtin(*f, args),
+    RispExp::Lambda(lambda) => {
+      let new_env = &mut env_for_lambda(lambda.params_exp.clone(), args, &lambda.env, name)?;
+      let mut result = RispExp::List(vec![]);
+      for form in lambda.body_exps.iter() {
+        result = eval(form, new_env)?;
+      }
+      Ok(result)
+    },
+    _ => Err(RispErr::Reason(format!("not a function: {}", f)))
+  }
+}
+
+fn eval_forms(arg_forms: &[RispExp], env: &mut RispEnv) -> Result<Vec<RispExp>, RispErr> {
+  arg_forms
+    .iter()
+    .map(|x| eval(x, env))
+    .collect()
+}
+
+fn eval(exp: &RispExp, env: &mut RispEnv) -> Result<RispExp, RispErr> {
+  match exp {
+    RispExp::Symbol(k) =>
+      env_get(k, env)
+      .ok_or(
+        RispErr::Reason(
+          format!("unexpected symbol k='{}'", k)
+        )
+      ),
+    RispExp::Str(_a) => Ok(exp.clone()),
+    RispExp::Bool(_a) => Ok(exp.clone()),
+    RispExp::Number(_a) => Ok(exp.clone()),
+
+    RispExp::List(list) => {
+      check_deadline()?;
+      check_interrupt()?;
+      let first_form = list
+        .first()
+        .ok_or(RispErr::Reason("expected a non-empty list".to_string()))?;
+      let arg_forms = &list[1..];
+      match eval_built_in_form(first_form, arg_forms, env) {
+        Some(res) => {
+          if let Ok(value) = &res {
+            step_reduced(exp, value);
+          }
+          res
+        },
+        None => {
+          let first_eval = eval(first_form, env)?;
+          match first_eval {
+            RispExp::Func(_) | RispExp::Lambda(_) => {
+              let args = eval_forms(arg_forms, env)?;
+              let reduced = RispExp::List(std::iter::once(first_form.clone()).chain(args.iter().cloned()).collect());
+              if arg_forms.iter().any(|form| matches!(form, RispExp::List(_))) {
+                step_reduced(exp, &reduced);
+              }
+              let res = traced_apply(first_form, &first_eval, &args)?;
+              step_reduced(&reduced, &res);
+              Ok(res)
+            },
+            _ => Err(
+              RispErr::Reason(format!("first form must be a function: {}", first_eval))
+            ),
+          }
+        }
+      }
+    },
+    RispExp::Func(_) => Err(RispErr::Reason("unexpected form".to_string())),
+    RispExp::Lambda(_) => Err(RispErr::Reason("unexpected form".to_string())),
+    RispExp::Json(_) => Ok(exp.clone()),
+    RispExp::Range(_) => Ok(exp.clone()),
+  }
+}
+
+/*
+  Repl
+*/
+
+// Every form on the line is evaluated, a form spread over several
+// lines included, and the value of the last one is the result
+fn parse_eval(expr: String, env: &mut RispEnv) -> Option<Result<RispExp, RispErr>> {
+  let tokens = &match tokenize(expr) {
+    Ok(tokens) => tokens,
+    Err(e) => return Some(Err(e)),
+  };
+  if tokens.len() < 1 {
+    return None;
+  }
+
+  Some(eval_tokens(tokens, env))
+}
+
+// Evaluate every form in expr, returning the value of the last one
+fn eval_all(expr: String, env: &mut RispEnv) -> Result<RispExp, RispErr> {
+  eval_tokens(&tokenize(expr)?, env)
+}
+
+fn eval_tokens(tokens: &[String], env: &mut RispEnv) -> Result<RispExp, RispErr> {
+  let mut rest = tokens;
+  let mut result = RispExp::List(vec![]);
+  while !rest.is_empty() {
+    let (exp, new_rest) = parse(rest)?;
+    result = eval(&exp, env)?;
+    rest = new_rest;
+  }
+  Ok(result)
+}
+
+fn load_file(path: &str, env: &mut RispEnv) -> Result<RispExp, RispErr> {
+  let text = fs::read_to_string(path)
+    .map_err(|e| RispErr::Reason(format!("{}: {}", path, e)))?;
+  eval_all(text, env)
+}
+
+// Lines that are entirely a known command after this prefix go to the
+// REPL rather than being evaluated, so :foo keywords still work
+const META_PREFIX: &str = ":";
+
+#[derive(Debug, PartialEq)]
+enum ReplCommand {
+  Quit,
+  Load(String),
+  Env,
+  Clear,
+  Doc(String),
+}
+
+fn repl_command(line: &str) -> Option<ReplCommand> {
+  let command = line.trim().strip_prefix(META_PREFIX)?;
+  let (name, arg) = match command.split_once(' ') {
+    Some((name, arg)) => (name, arg.trim()),
+    None => (command, ""),
+  };
+  match (name, arg) {
+    ("quit", "") => Some(ReplCommand::Quit),
+    ("load", path) if !path.is_empty() => Some(ReplCommand::Load(path.to_string())),
+    ("env", "") => Some(ReplCommand::Env),
+    ("clear", "") => Some(ReplCommand::Clear),
+    ("doc", name) if !name.is_empty() => Some(ReplCommand::Doc(name.to_string())),
+    _ => None,
+  }
+}
+
+// ANSI colour by type for results at a terminal, plain text otherwise
+fn color_result(res: &Result<RispExp, RispErr>, tty: bool) -> String {
+  let (text, color) = match res {
+    Ok(exp) => (exp.to_string(), match exp {
+      RispExp::Number(_) => Some("36"),
+      RispExp::Str(_) => Some("32"),
+      RispExp::Bool(_) => Some("33"),
+      RispExp::Symbol(_) => Some("35"),
+      _ => None,
+    }),
+    Err(RispErr::Reason(msg)) => (msg.clone(), Some("31")),
+  };
+  match color {
+    Some(code) if tty => format!("=> \x1b[{}m{}\x1b[0m", code, text),
+    _ => format!("=> {}", text),
+  }
+}
+
+#[derive(Debug)]
+enum RispIOErr {
+  Reason(String),
+}
+  
+fn slurp_expr() -> Result<String, RispIOErr> {
+  let mut expr = String::new();
+  
+  let red = io::stdin().read_line(&mut expr)
+    .expect("Failed to read line");
+
+  if red == 0 {
+    println!(); // newline to clear up the terminal
+    return Err(RispIOErr::Reason("EOF".to_string()));
+  }
+
+  Ok(expr)
+}
+
+// How many `(`s are still waiting for their `)`, going by the tokens
+// so parens in strings and comments don't count. Input that doesn't
+// tokenize is left for eval to report.
+fn open_forms(expr: &str) -> usize {
+  match tokenize(expr.to_string()) {
+    Ok(tokens) => tokens.iter().fold(0, |depth: usize, token| match token.as_str() {
+      "(" => depth + 1,
+      ")" => depth.saturating_sub(1),
+      _ => depth,
+    }),
+    Err(_) => 0,
+  }
+}
+
+// Keeps reading lines onto expr until every form in it is closed
+fn slurp_rest(mut expr: String) -> Result<String, RispIOErr> {
+  while open_forms(&expr) > 0 {
+    print!("...> ");
+    io::stdout().flush().unwrap();
+    match slurp_expr() {
+      Ok(line) => expr.push_str(&line),
+      Err(_) => return Err(RispIOErr::Reason("end of input before the form was closed".to_string())),
+    }
+  }
+  Ok(expr)
+}
+
+struct Options {
+  exprs: Vec<String>,
+  json: bool,
+  lenient: bool,
+  strict: bool,
+  approx: bool,
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+  let mut options = Options { exprs: Vec::new(), json: false, lenient: false, strict: false, approx: false };
+  let mut args = args.iter();
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "-e" | "--eval" => {
+        let expr = args.next().ok_or(format!("{} needs an expression", arg))?;
+        options.exprs.push(expr.clone());
+      },
+      "--json" => options.json = true,
+      "--lenient" => options.lenient = true,
+      "--strict" => options.strict = true,
+      "--approx" => options.approx = true,
+      _ => return Err(format!("unknown argument {}", arg)),
+    }
+  }
+  Ok(options)
+}
+
+fn output(res: &RispExp, json: bool) -> Result<String, RispErr> {
+  if json {
+    Ok(jsontypes::from_risp(res)?.to_string())
+  } else {
+    Ok(res.to_string())
+  }
+}
+
+// Each -e shares the one env, the first error stops everything
+fn eval_exprs(exprs: &[String], json: bool, env: &mut RispEnv) -> i32 {
+  for expr in exprs {
+    match eval_all(expr.clone(), env).and_then(|res| output(&res, json)) {
+      Ok(res) => println!("{}", res),
+      Err(RispErr::Reason(msg)) => {
+        eprintln!("{}", msg);
+        return 1;
+      },
+    }
+  }
+  0
 }
 
 fn main() {
+  let args: Vec<String> = std::env::args().skip(1).collect();
+  let options = match parse_options(&args) {
+    Ok(options) => options,
+    Err(msg) => {
+      eprintln!("{}", msg);
+      process::exit(2);
+    },
+  };
+  STRICT.with(|s| s.set(options.strict));
+  math::APPROX_EQUAL.store(options.approx, Ordering::Relaxed);
   let env = &mut default_env();
+  if !options.exprs.is_empty() {
+    process::exit(eval_exprs(&options.exprs, options.json, env));
+  }
+  if !io::stdin().is_terminal() {
+    process::exit(eval_stdin(options.json, env));
+  }
+  load_startup_file(env);
+  repl(env, options.lenient);
+}
+
+// A program piped in is evaluated as a whole, only the last value is printed
+fn eval_stdin(json: bool, env: &mut RispEnv) -> i32 {
+  let mut program = String::new();
+  if let Err(e) = io::stdin().read_to_string(&mut program) {
+    eprintln!("{}", e);
+    return 1;
+  }
+  eval_exprs(&[program], json, env)
+}
+
+// $NICRISPRC or else ~/.nicrisprc
+fn startup_file() -> Option<String> {
+  match std::env::var("NICRISPRC") {
+    Ok(path) => Some(path),
+    Err(_) => std::env::var("HOME").ok().map(|home| format!("{}/.nicrisprc", home)),
+  }
+}
+
+// A missing startup file is fine but one that fails to load is reported
+fn load_startup_file(env: &mut RispEnv) {
+  let path = match startup_file() {
+    Some(path) => path,
+    None => return,
+  };
+  if !std::path::Path::new(&path).exists() {
+    return;
+  }
+  if let Err(RispErr::Reason(msg)) = load_file(&path, env) {
+    eprintln!("error loading {}: {}", path, msg);
+  }
+}
+
+// Ctrl-C while evaluating interrupts it, a second one before eval
+// notices, or any at the prompt after the first, exits
+fn handle_ctrl_c() {
+  if INTERRUPTED.swap(true, Ordering::SeqCst) {
+    println!();
+    process::exit(130);
+  }
+  if !EVALUATING.load(Ordering::SeqCst) {
+    print!("\n(Ctrl-C again to quit)\nrisp> ");
+    io::stdout().flush().unwrap();
+  }
+}
+
+// The expression with enough `)`s on the end to close every open
+// form, or None if nothing is left open. It counts with open_forms so
+// it agrees with the multi-line REPL about what's open, and the
+// closing parens go on a new line if the last line ends in a comment.
+fn auto_close(expr: &str) -> Option<String> {
+  let depth = open_forms(expr);
+  if depth == 0 {
+    return None;
+  }
+  let closed = format!("{}{}", expr.trim_end(), ")".repeat(depth));
+  if open_forms(&closed) == 0 {
+    return Some(closed);
+  }
+  Some(format!("{}\n{}", expr.trim_end(), ")".repeat(depth)))
+}
+
+fn repl(env: &mut RispEnv, lenient: bool) {
+  let tty = io::stdout().is_terminal();
+  if let Err(e) = ctrlc::set_handler(handle_ctrl_c) {
+    eprintln!("could not install Ctrl-C handler: {}", e);
+  }
   loop {
     print!("risp> ");
     io::stdout().flush().unwrap();
-    match slurp_expr() {
+    let line = slurp_expr();
+    INTERRUPTED.store(false, Ordering::SeqCst);
+    match line {
       Ok(expr) => {
-	match parse_eval(expr, env) {
-	  Some(res) => match res {
-	    Ok(res) => println!("=> {}", res),
-	    Err(e) => match e {
-              RispErr::Reason(msg) => println!("=> {}", msg),
-	    },
-	  },
+        if let Some(command) = repl_command(&expr) {
+          match command {
+            ReplCommand::Quit => break,
+            ReplCommand::Load(path) => {
+              EVALUATING.store(true, Ordering::SeqCst);
+              let res = load_file(&path, env);
+              EVALUATING.store(false, Ordering::SeqCst);
+              println!("{}", color_result(&res, tty));
+            },
+            ReplCommand::Env => {
+              let mut names: Vec<String> = env_bindings(env).into_keys().collect();
+              names.sort();
+              println!("{}", names.join(" "));
+            },
+            ReplCommand::Clear => *env = default_env(),
+            ReplCommand::Doc(name) => match builtin_doc(&name) {
+              Some(doc) => println!("{}", doc),
+              None => println!("no doc for {}", name),
+            },
+          }
+          continue;
+        }
+        let expr = match auto_close(&expr) {
+          Some(closed) if lenient => {
+            println!("{}", closed);
+            closed
+          },
+          _ => match slurp_rest(expr) {
+            Ok(expr) => expr,
+            Err(RispIOErr::Reason(e)) => {
+              println!("{}", color_result(&Err(RispErr::Reason(e)), tty));
+              break;
+            },
+          },
+        };
+        EVALUATING.store(true, Ordering::SeqCst);
+        let res = parse_eval(expr, env);
+        EVALUATING.store(false, Ordering::SeqCst);
+	match res {
+	  Some(res) => println!("{}", color_result(&res, tty)),
 	  None => println!(""),
 	};
       },
@@ -561,6 +1979,705 @@ fn main() {
   }
 }
 
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn run_in(expr: &str, env: &mut RispEnv) -> String {
+    match eval_all(expr.to_string(), env) {
+      Ok(res) => res.to_string(),
+      Err(RispErr::Reason(msg)) => panic!("{}: {}", expr, msg),
+    }
+  }
+
+  fn run(expr: &str) -> String {
+    run_in(expr, &mut default_env())
+  }
+
+  fn run_err(expr: &str) -> String {
+    match eval_all(expr.to_string(), &mut default_env()) {
+      Ok(res) => panic!("{}: expected an error, got {}", expr, res),
+      Err(RispErr::Reason(msg)) => msg,
+    }
+  }
+
+  // The warnings and progress, benchmark, step and trace lines f writes
+  fn captured<F: FnOnce()>(f: F) -> Vec<String> {
+    CAPTURED.with(|c| *c.borrow_mut() = Some(Vec::new()));
+    f();
+    CAPTURED.with(|c| c.borrow_mut().take().unwrap_or_default())
+  }
+
+  thread_local! {
+    static CALLS: Cell<usize> = const { Cell::new(0) };
+  }
+
+  // An env with (tick) to count calls, and (flaky) that fails until
+  // it has been called three times
+  fn counting_env() -> RispEnv {
+    CALLS.with(|c| c.set(0));
+    let env = default_env();
+    env.data.borrow_mut().insert("tick".to_string(), RispExp::Func(|_| {
+      CALLS.with(|c| c.set(c.get() + 1));
+      Ok(RispExp::Bool(true))
+    }));
+    env.data.borrow_mut().insert("flaky".to_string(), RispExp::Func(|_| {
+      let calls = CALLS.with(|c| {
+        c.set(c.get() + 1);
+        c.get()
+      });
+      if calls < 3 {
+        return Err(RispErr::Reason("not yet".to_string()));
+      }
+      Ok(RispExp::Number(calls as f64))
+    }));
+    env
+  }
+
+  fn calls() -> usize {
+    CALLS.with(|c| c.get())
+  }
+
+  #[test]
+  fn list_car_and_cdr_are_builtins() {
+    let env = &mut default_env();
+    assert_eq!(parse_eval("(list 1 2 3)".to_string(), env).unwrap().ok().unwrap().to_string(), "(1,2,3)");
+    assert_eq!(parse_eval("(car (list 1 2 3))".to_string(), env).unwrap().ok().unwrap().to_string(), "1");
+    assert_eq!(parse_eval("(cdr (list 1 2 3))".to_string(), env).unwrap().ok().unwrap().to_string(), "(2,3)");
+    assert_eq!(run("(car (cdr (list 1 2 3)))"), "2");
+    assert_eq!(run_err("(car 1)"), "arg is not a list: 1");
+  }
+
+  #[test]
+  fn with_timeout_gives_up_on_a_blocking_form() {
+    let start = Instant::now();
+    assert_eq!(run_err("(with-timeout 50 (sleep 5000))"), "timed out");
+    assert!(start.elapsed() < Duration::from_secs(2));
+  }
+
+  #[test]
+  fn with_timeout_returns_a_form_that_finishes() {
+    assert_eq!(run("(with-timeout 1000 (begin (sleep 10) (+ 1 2)))"), "3");
+    assert_eq!(run_err("(with-timeout 1000 (car 1))"), "arg is not a list: 1");
+    assert_eq!(run_err("(with-timeout -1 1)"), "timeout must be a positive number: -1");
+  }
+
+  #[test]
+  fn with_timeout_stops_a_busy_form() {
+    assert_eq!(run_err("(with-timeout 10 (until false 1))"), "timed out");
+  }
+
+  #[test]
+  fn with_timeout_keeps_the_sooner_deadline() {
+    let start = Instant::now();
+    assert_eq!(run_err("(with-timeout 50 (with-timeout 60000 (sleep 5000)))"), "timed out");
+    assert!(start.elapsed() < Duration::from_secs(2));
+  }
+
+  #[test]
+  fn with_timeout_sees_the_callers_bindings() {
+    let env = &mut default_env();
+    run_in("(def x 2) (def fact (fn (n) (if (= n 0) 1 (* n (fact (- n 1))))))", env);
+    assert_eq!(run_in("(with-timeout 1000 (* x (fact 5)))", env), "240");
+    assert_eq!(run_in("((with-timeout 1000 (fn (y) (+ x y))) 1)", env), "3");
+    assert_eq!(run_in("(get (with-timeout 1000 (list->json (list 1 2))) 1)", env), "2");
+  }
+
+  #[test]
+  fn with_timeout_defs_stay_in_the_worker() {
+    let env = &mut default_env();
+    assert_eq!(run_in("(with-timeout 1000 (begin (def y 1) y))", env), "1");
+    assert_eq!(run_in("(defined? (quote y))", env), "false");
+  }
+
+  #[test]
+  fn defined_reports_bindings() {
+    let env = &mut default_env();
+    assert_eq!(run_in("(defined? (quote x))", env), "false");
+    run_in("(def x 1)", env);
+    assert_eq!(run_in("(defined? (quote x))", env), "true");
+    assert_eq!(run_in("(defined? (quote +))", env), "true");
+  }
+
+  #[test]
+  fn defined_and_undef() {
+    let env = &mut default_env();
+    assert_eq!(run_in("(defined? (quote x))", env), "false");
+    run_in("(def x 1)", env);
+    assert_eq!(run_in("(defined? (quote x))", env), "true");
+    assert_eq!(run_in("(undef x)", env), "true");
+    assert_eq!(run_in("(undef x)", env), "false");
+    assert_eq!(run_in("(defined? (quote x))", env), "false");
+  }
+
+  #[test]
+  fn undef_leaves_outer_bindings() {
+    let env = &mut default_env();
+    run_in("(def x 1) (def f (fn () (undef x)))", env);
+    assert_eq!(run_in("(f)", env), "false");
+    assert_eq!(run_in("x", env), "1");
+  }
+
+  #[test]
+  fn bindings_lists_names_and_values() {
+    let env = &mut RispEnv::new(HashMap::new(), None);
+    env.data.borrow_mut().insert("b".to_string(), RispExp::Number(2.0));
+    env.data.borrow_mut().insert("a".to_string(), RispExp::Number(1.0));
+    let inner = &mut RispEnv::new(HashMap::new(), Some(env));
+    inner.data.borrow_mut().insert("b".to_string(), RispExp::Number(3.0));
+    assert_eq!(run_in("(bindings)", inner), "((a,1),(b,3))");
+  }
+
+  #[test]
+  fn json_bindings_share_their_value() {
+    let env = &mut default_env();
+    let big: Vec<serde_json::Value> = (0..100_000).map(serde_json::Value::from).collect();
+    let data = Rc::new(serde_json::Value::Array(big));
+    env.data.borrow_mut().insert("j".to_string(), RispExp::Json(data.clone()));
+    let j = RispExp::Symbol("j".to_string());
+    for _ in 0..10_000 {
+      match eval(&j, env) {
+        Ok(RispExp::Json(value)) => {
+          assert!(Rc::ptr_eq(&value, &data));
+          // the binding, this test's handle and the value just evaluated
+          assert_eq!(Rc::strong_count(&data), 3);
+        }
+        _ => panic!("not json"),
+      }
+    }
+    assert_eq!(Rc::strong_count(&data), 2);
+  }
+
+  #[test]
+  fn assoc_update_applies_to_the_value() {
+    assert_eq!(
+      run("(assoc-update (list (list \"a\" 1)) \"a\" (fn (v) (+ v 1)))"),
+      "((\"a\",2))"
+    );
+    assert_eq!(
+      run("(assoc-update (list (list \"a\" 1)) \"b\" (fn (v) (if v v 0)))"),
+      "((\"a\",1),(\"b\",0))"
+    );
+  }
+
+  #[test]
+  fn repl_commands() {
+    assert_eq!(repl_command(":quit\n"), Some(ReplCommand::Quit));
+    assert_eq!(repl_command(":load lib.lisp"), Some(ReplCommand::Load("lib.lisp".to_string())));
+    assert_eq!(repl_command(":env"), Some(ReplCommand::Env));
+    assert_eq!(repl_command("  :clear  "), Some(ReplCommand::Clear));
+    assert_eq!(repl_command(":doc car"), Some(ReplCommand::Doc("car".to_string())));
+  }
+
+  #[test]
+  fn repl_commands_leave_keywords_alone() {
+    assert_eq!(repl_command(":foo"), None);
+    assert_eq!(repl_command(":load"), None);
+    assert_eq!(repl_command(":quit now"), None);
+    assert_eq!(repl_command("(car (list :a))"), None);
+    assert_eq!(run(":foo"), ":foo");
+  }
+
+  #[test]
+  fn color_result_at_a_tty() {
+    assert_eq!(color_result(&Ok(RispExp::Number(1.0)), true), "=> \x1b[36m1\x1b[0m");
+    assert_eq!(color_result(&Ok(RispExp::Str("a".to_string())), true), "=> \x1b[32m\"a\"\x1b[0m");
+    assert_eq!(color_result(&Ok(RispExp::Bool(true)), true), "=> \x1b[33mtrue\x1b[0m");
+    assert_eq!(color_result(&Ok(RispExp::Symbol("x".to_string())), true), "=> \x1b[35mx\x1b[0m");
+    assert_eq!(color_result(&Err(RispErr::Reason("bad".to_string())), true), "=> \x1b[31mbad\x1b[0m");
+    assert_eq!(color_result(&Ok(RispExp::List(vec![])), true), "=> ()");
+  }
+
+  #[test]
+  fn color_result_without_a_tty() {
+    assert_eq!(color_result(&Ok(RispExp::Number(1.0)), false), "=> 1");
+    assert_eq!(color_result(&Ok(RispExp::Str("a".to_string())), false), "=> \"a\"");
+    assert_eq!(color_result(&Err(RispErr::Reason("bad".to_string())), false), "=> bad");
+  }
+
+  #[test]
+  fn options() {
+    let args: Vec<String> = ["-e", "(+ 1 2)", "--eval", "3", "--json"].iter().map(|s| s.to_string()).collect();
+    let options = parse_options(&args).ok().unwrap();
+    assert_eq!(options.exprs, vec!["(+ 1 2)", "3"]);
+    assert!(options.json);
+    assert!(matches!(parse_options(&["-e".to_string()]), Err(msg) if msg == "-e needs an expression"));
+    assert!(matches!(parse_options(&["--what".to_string()]), Err(msg) if msg == "unknown argument --what"));
+  }
+
+  #[test]
+  fn json_output() {
+    assert_eq!(output(&RispExp::List(vec![RispExp::Number(1.0), RispExp::Str("a".to_string())]), true).ok(), Some("[1,\"a\"]".to_string()));
+    assert_eq!(output(&RispExp::Number(1.5), false).ok(), Some("1.5".to_string()));
+  }
+
+  // A flag of its own, INTERRUPTED is shared by every test thread
+  #[test]
+  fn interrupt_is_taken_once() {
+    let flag = AtomicBool::new(false);
+    assert!(take_interrupt(&flag).is_ok());
+    flag.store(true, Ordering::SeqCst);
+    assert!(matches!(take_interrupt(&flag), Err(RispErr::Reason(msg)) if msg == "interrupted"));
+    assert!(take_interrupt(&flag).is_ok());
+  }
+
+  #[test]
+  fn letfn_functions_call_each_other() {
+    let env = &mut default_env();
+    let expr = "(letfn ((ev (n) (if (= n 0) true (od (- n 1)))) (od (n) (if (= n 0) false (ev (- n 1))))) (ev 10))";
+    assert_eq!(run_in(expr, env), "true");
+    assert_eq!(run_in("(defined? (quote ev))", env), "false");
+  }
+
+  #[test]
+  fn trace_shows_nested_calls() {
+    let env = &mut default_env();
+    run_in("(def sq (fn (x) (* x x)))", env);
+    let lines = captured(|| {
+      run_in("(trace (+ (sq 2) 1))", env);
+    });
+    assert_eq!(lines, vec!["(sq 2)", "  (* 2 2)", "  => 4", "=> 4", "(+ 4 1)", "=> 5"]);
+    let errors = captured(|| {
+      assert!(eval_all("(trace (car 5))".to_string(), env).is_err());
+    });
+    assert_eq!(errors, vec!["(car 5)", "!! arg is not a list: 5"]);
+  }
+
+  #[test]
+  fn startup_file_is_loaded() {
+    let path = std::env::temp_dir().join(format!("risp-startup-{}.lisp", process::id()));
+    fs::write(&path, "(def from-startup 42)\n").unwrap();
+    std::env::set_var("NICRISPRC", &path);
+    let env = &mut default_env();
+    load_startup_file(env);
+    std::env::remove_var("NICRISPRC");
+    fs::remove_file(&path).unwrap();
+    assert_eq!(run_in("from-startup", env), "42");
+  }
+
+  #[test]
+  fn display_distinguishes_whole_numbers() {
+    assert_eq!(run("(/ 10 4)"), "2.5");
+    assert_eq!(run("(* 2 3)"), "6");
+    assert_eq!(run("(+ 0.1 0.2)"), "0.30000000000000004");
+  }
+
+  #[test]
+  fn numbers_are_written_out_in_full() {
+    assert_eq!(run("(concat \"n=\" (+ 0.1 0.2))"), "\"n=0.30000000000000004\"");
+    assert_eq!(run("(join (list (/ 1 3)) \",\")"), "\"0.3333333333333333\"");
+    assert_eq!(run("(write-csv (list (list 123456.78901234567)))"), "\"123456.78901234567\n\"");
+  }
+
+  #[test]
+  fn apply_times_composes() {
+    assert_eq!(run("(apply-times 3 (fn (x) (* x 2)) 1)"), "8");
+    assert_eq!(run("(apply-times 0 (fn (x) (* x 2)) 1)"), "1");
+    assert_eq!(run_err("(apply-times 1.5 (fn (x) x) 1)"), "count must be a non-negative integer, got 1.5");
+  }
+
+  #[test]
+  fn defconst_cannot_be_changed() {
+    let env = &mut default_env();
+    run_in("(defconst pi 3.14)", env);
+    assert!(matches!(eval_all("(def pi 3)".to_string(), env), Err(RispErr::Reason(msg)) if msg == "cannot reassign constant pi"));
+    assert!(matches!(eval_all("(undef pi)".to_string(), env), Err(RispErr::Reason(msg)) if msg == "cannot undef constant pi"));
+    assert_eq!(run_in("pi", env), "3.14");
+  }
+
+  #[test]
+  fn do_while_and_until() {
+    assert_eq!(run("(def n 0) (do-while (def n (+ n 1)) n (< n 3))"), "3");
+    assert_eq!(run("(def n 10) (do-while (def n (+ n 1)) n (< n 3))"), "11");
+    assert_eq!(run("(def n 0) (until (= n 4) (def n (+ n 1)) n)"), "4");
+    assert_eq!(run("(until true 1)"), "()");
+  }
+
+  #[test]
+  fn binding_is_seen_by_called_functions() {
+    let env = &mut default_env();
+    run_in("(def *depth* 1) (def show (fn () *depth*))", env);
+    assert_eq!(run_in("(binding (*depth* 2) (show))", env), "2");
+    assert_eq!(run_in("(show)", env), "1");
+  }
+
+  #[test]
+  fn binding_restores_after_an_error() {
+    let env = &mut default_env();
+    run_in("(def x 1)", env);
+    assert!(eval_all("(binding (x 2) (car 5))".to_string(), env).is_err());
+    assert_eq!(run_in("x", env), "1");
+    assert!(eval_all("(binding (fresh 1) (car 5))".to_string(), env).is_err());
+    assert_eq!(run_in("(defined? (quote fresh))", env), "false");
+  }
+
+  #[test]
+  fn binding_refuses_constants() {
+    let env = &mut default_env();
+    run_in("(defconst k 1) (def f (fn () (binding (k 2) k)))", env);
+    assert!(matches!(eval_all("(f)".to_string(), env), Err(RispErr::Reason(msg)) if msg == "cannot rebind constant k"));
+    assert_eq!(run_in("k", env), "1");
+  }
+
+  #[test]
+  fn partition_by_runs() {
+    assert_eq!(run("(partition-by odd? (list 1 3 2 4 5))"), "((1,3),(2,4),(5))");
+    assert_eq!(run("(partition-by odd? nil)"), "()");
+  }
+
+  #[test]
+  fn sort_by_a_lambda_key() {
+    let env = &mut default_env();
+    run_in("(def people (list (list \"ann\" 31) (list \"bob\" 25) (list \"cy\" 31) (list \"di\" 25)))", env);
+    assert_eq!(
+      run_in("(sort-by (fn (p) (car (cdr p))) people)", env),
+      "((\"bob\",25),(\"di\",25),(\"ann\",31),(\"cy\",31))"
+    );
+    assert_eq!(run_in("(sort-by (fn (p) (car p)) people)", env), run_in("people", env));
+  }
+
+  #[test]
+  fn merge_with_combines_conflicts() {
+    assert_eq!(
+      run("(merge-with + (list (list \"a\" 1) (list \"b\" 2)) (list (list \"b\" 3) (list \"c\" 4)))"),
+      "((\"a\",1),(\"b\",5),(\"c\",4))"
+    );
+    assert_eq!(run_err("(merge-with + (list) 5)"), "not an assoc list: 5");
+  }
+
+  #[test]
+  fn errors_show_the_offending_value() {
+    assert_eq!(run_err("(+ 1 \"a\")"), "expected a number: \"a\"");
+    assert_eq!(run_err("(car 5)"), "arg is not a list: 5");
+    assert_eq!(run_err("(1 2)"), "first form must be a function: 1");
+    assert_eq!(run_err("nope"), "unexpected symbol k='nope'");
+  }
+
+  #[test]
+  fn get_is_a_builtin() {
+    assert_eq!(run("(get (list->json (list 10 20)) 1)"), "20");
+    assert_eq!(run("(get (list->json (list (list \"a\" 1))) \"a\")"), "1");
+    assert_eq!(run_err("(get (list->json (list 10 20)) -1)"), "index must be a non-negative integer: -1");
+  }
+
+  #[test]
+  fn auto_close_adds_missing_parens() {
+    assert_eq!(auto_close("(+ 1 2)"), None);
+    assert_eq!(auto_close("(+ 1 2\n"), Some("(+ 1 2)".to_string()));
+    assert_eq!(auto_close("(+ 1 (* 2 3"), Some("(+ 1 (* 2 3))".to_string()));
+    assert_eq!(auto_close("(concat \")\""), Some("(concat \")\")".to_string()));
+    assert_eq!(auto_close("(+ 1 2 ; sum"), Some("(+ 1 2 ; sum\n)".to_string()));
+    assert_eq!(run(&auto_close("(+ 1 (* 2 3").unwrap()), "7");
+  }
+
+  #[test]
+  fn def_warns_about_builtins() {
+    let env = &mut default_env();
+    let warnings = captured(|| {
+      assert_eq!(run_in("(def + (fn (a b) (* a b)))", env), "+");
+    });
+    assert_eq!(warnings, vec!["warning: redefining builtin +"]);
+    assert_eq!(run_in("(+ 2 3)", env), "6");
+  }
+
+  #[test]
+  fn strict_def_refuses_builtins() {
+    let env = &mut default_env();
+    STRICT.with(|s| s.set(true));
+    let warnings = captured(|| {
+      let res = eval_all("(def + (fn (a b) (* a b)))".to_string(), env);
+      assert!(matches!(res, Err(RispErr::Reason(msg)) if msg == "cannot redefine builtin +"));
+    });
+    STRICT.with(|s| s.set(false));
+    assert!(warnings.is_empty());
+    assert_eq!(run_in("(+ 2 3)", env), "5");
+  }
+
+  #[test]
+  fn parse_atom_numbers_and_symbols() {
+    assert_eq!(parse_atom("-3").to_string(), "-3");
+    assert!(matches!(parse_atom("-3"), RispExp::Number(n) if n == -3.0));
+    assert!(matches!(parse_atom("-.5"), RispExp::Number(n) if n == -0.5));
+    assert!(matches!(parse_atom("+2"), RispExp::Number(n) if n == 2.0));
+    assert!(matches!(parse_atom("-"), RispExp::Symbol(s) if s == "-"));
+    assert!(matches!(parse_atom("->"), RispExp::Symbol(s) if s == "->"));
+    assert!(matches!(parse_atom("->foo"), RispExp::Symbol(s) if s == "->foo"));
+    assert!(matches!(parse_atom("inf"), RispExp::Symbol(s) if s == "inf"));
+    assert!(matches!(parse_atom("true"), RispExp::Bool(true)));
+    assert!(matches!(parse_atom("\"hi\""), RispExp::Str(s) if s == "hi"));
+  }
+
+  #[test]
+  fn negative_literals() {
+    assert_eq!(run("(+ -3 1)"), "-2");
+    assert_eq!(run("(- 5 -.5)"), "5.5");
+    assert_eq!(run("(- 5 3)"), "2");
+    assert_eq!(run("(def -> 1) ->"), "1");
+  }
+
+  #[test]
+  fn repeat_passes_the_index() {
+    assert_eq!(run("(repeat (fn (x) (* x x)) (list 1 2 3))"), "(1,4,9)");
+    assert_eq!(run("(repeat (fn (x i) (+ x i)) (list 10 10 10))"), "(10,11,12)");
+  }
+
+  #[test]
+  fn quoted_symbols() {
+    assert_eq!(run("(def |a b(c)| 1) |a b(c)|"), "1");
+    assert_eq!(run("(quote |x;y|)"), "x;y");
+    assert_eq!(run_err("(quote |open"), "unterminated `|` in symbol");
+  }
+
+  #[test]
+  fn comments_are_skipped() {
+    assert_eq!(run("(+ 1 ; one\n 2) # two"), "3");
+  }
+
+  #[test]
+  fn and_or_short_circuit() {
+    let env = &mut counting_env();
+    assert_eq!(run_in("(and false (tick))", env), "false");
+    assert_eq!(run_in("(or 1 (tick))", env), "1");
+    assert_eq!(calls(), 0);
+    assert_eq!(run_in("(and (tick) (tick) 7)", env), "7");
+    assert_eq!(run_in("(or false nil (tick))", env), "true");
+    assert_eq!(calls(), 3);
+    assert_eq!(run_in("(list (and) (or))", env), "(true,false)");
+  }
+
+  #[test]
+  fn not_and_truthiness() {
+    assert_eq!(run("(list (not false) (not nil) (not 0) (not \"\") (not (list 1)))"), "(true,true,false,false,false)");
+    assert_eq!(run("(if nil 1 2)"), "2");
+    assert_eq!(run("(if 0 1 2)"), "1");
+    assert_eq!(run_err("(not 1 2)"), "not takes a single value");
+  }
+
+  #[test]
+  fn retry_backoff_until_success() {
+    let env = &mut counting_env();
+    assert_eq!(run_in("(with-retry-backoff 5 (flaky) 1 2)", env), "3");
+    assert_eq!(calls(), 3);
+  }
+
+  #[test]
+  fn retry_backoff_gives_up() {
+    let env = &mut counting_env();
+    assert!(matches!(
+      eval_all("(with-retry-backoff 2 (flaky) 1 2)".to_string(), env),
+      Err(RispErr::Reason(msg)) if msg == "not yet"
+    ));
+    assert_eq!(calls(), 2);
+  }
+
+  #[test]
+  fn retry_delays_and_jitter() {
+    let env = &mut default_env();
+    assert_eq!(eval_delay(None, 100, env).ok(), Some(Duration::from_millis(100)));
+    assert_eq!(eval_delay(Some(&RispExp::Number(5.0)), 100, env).ok(), Some(Duration::from_millis(5)));
+    assert!(eval_delay(Some(&RispExp::Number(-1.0)), 100, env).is_err());
+    let delay = Duration::from_millis(100);
+    assert!(jitter(delay) <= delay / 2);
+  }
+
+  #[test]
+  fn lambdas_close_over_their_env() {
+    let env = &mut default_env();
+    run_in("(def make-adder (fn (n) (fn (x) (+ x n))))", env);
+    run_in("(def add2 (make-adder 2))", env);
+    assert_eq!(run_in("(add2 5)", env), "7");
+  }
+
+  #[test]
+  fn aggregates_agree_on_ranges_and_lists() {
+    for args in ["10", "10 3", "10 0 3", "0 10 -3", "0"] {
+      for f in ["count", "sum", "min", "max"] {
+        let lazy = eval_all(format!("({} (range {}))", f, args), &mut default_env());
+        let eager = eval_all(format!("({} (num {}))", f, args), &mut default_env());
+        match (lazy, eager) {
+          (Ok(lazy), Ok(eager)) => assert_eq!(lazy.to_string(), eager.to_string()),
+          (Err(RispErr::Reason(lazy)), Err(RispErr::Reason(eager))) => assert_eq!(lazy, eager),
+          _ => panic!("({} (range {})) and ({} (num {})) differ", f, args, f, args),
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn aggregates_dont_realize_ranges() {
+    // a list this long wouldn't fit in memory
+    assert_eq!(run("(count (range 1000000000000))"), "1000000000000");
+    assert_eq!(run("(max (range 1000000000000))"), "999999999999");
+    assert_eq!(run("(min (range 0 1000000000000 -1))"), "1");
+    assert_eq!(run("(sum (range 10000000))"), "49999995000000");
+  }
+
+  #[test]
+  fn lambda_bodies_run_in_order() {
+    assert_eq!(run("((fn (x) (def y (* x 2)) (+ y 1)) 5)"), "11");
+    assert_eq!(run_err("(fn (x))"), "expected body forms");
+  }
+
+  #[test]
+  fn begin_and_do() {
+    assert_eq!(run("(begin (def a 1) (def b 2) (+ a b))"), "3");
+    assert_eq!(run("(do 1 2)"), "2");
+    assert_eq!(run_err("(begin)"), "begin expects at least one form");
+  }
+
+  #[test]
+  fn repeat_reports_progress() {
+    let env = &mut default_env();
+    run_in("(def *progress* 2)", env);
+    let lines = captured(|| {
+      run_in("(repeat (fn (x) x) (list 1 2 3 4 5))", env);
+    });
+    assert_eq!(lines, vec!["2/5", "4/5", "5/5"]);
+    let quiet = captured(|| {
+      run("(repeat (fn (x) x) (list 1 2 3))");
+    });
+    assert!(quiet.is_empty());
+  }
+
+  #[test]
+  fn map_reports_progress() {
+    let env = &mut default_env();
+    run_in("(def *progress* 2)", env);
+    let lines = captured(|| {
+      assert_eq!(run_in("(map (fn (x) (* x 10)) (list 1 2 3 4 5))", env), "(10,20,30,40,50)");
+    });
+    assert_eq!(lines, vec!["2/5", "4/5", "5/5"]);
+    let passed = captured(|| {
+      assert_eq!(run_in("(apply-times 1 (fn (xs) ((car (list map)) car xs)) (list (list 1) (list 2)))", env), "(1,2)");
+    });
+    assert!(passed.is_empty());
+  }
+
+  #[test]
+  fn map_is_a_function() {
+    assert_eq!(run("(map (fn (x) (+ x 1)) (list 1 2))"), "(2,3)");
+    assert_eq!(run("(map car (list (list 1) (list 2)))"), "(1,2)");
+    assert_eq!(run("(def m map) (m (fn (x) (* x 2)) (list 1 2))"), "(2,4)");
+  }
+
+  #[test]
+  fn filter_with_a_lambda() {
+    assert_eq!(run("(filter (fn (x) (> x 1)) (list 1 2 3))"), "(2,3)");
+  }
+
+  #[test]
+  fn arity_errors_name_the_function() {
+    assert_eq!(run_err("(def f (fn (a b) a)) (f 1)"), "f: expected 2 arguments, got 1");
+    assert_eq!(run_err("((fn (a) a))"), "expected 1 argument, got 0");
+  }
+
+  #[test]
+  fn reduce_folds_a_list() {
+    assert_eq!(run("(reduce + 0 (list 1 2 3))"), "6");
+    assert_eq!(run("(reduce (fn (acc x) (cons x acc)) nil (list 1 2 3))"), "(3,2,1)");
+    assert_eq!(run("(reduce + 5 nil)"), "5");
+    assert_eq!(run_err("(reduce (fn (x) x) 0 (list 1))"), "reduce needs a function of two arguments");
+  }
+
+  #[test]
+  fn benchmark_returns_the_last_value() {
+    let env = &mut default_env();
+    run_in("(def n 0)", env);
+    let lines = captured(|| {
+      assert_eq!(run_in("(benchmark 5 (begin (def n (+ n 1)) n))", env), "5");
+    });
+    assert_eq!(lines.len(), 1);
+    assert!(lines[0].starts_with("5 iterations in "));
+    assert_eq!(run_err("(benchmark 0 1)"), "iterations must be a positive integer: 0");
+  }
+
+  #[test]
+  fn quasiquote_and_splicing() {
+    assert_eq!(run("(def x 2) `(1 ,x 3)"), "(1,2,3)");
+    assert_eq!(run("(def xs (list 2 3)) `(1 ,@xs 4)"), "(1,2,3,4)");
+    assert_eq!(run("`(1 ,@nil 2)"), "(1,2)");
+    assert_eq!(run_err("`(1 ,@2)"), "can only splice a list: 2");
+    assert_eq!(run_err(",x"), "unquote outside quasiquote");
+  }
+
+  #[test]
+  fn step_shows_each_reduction() {
+    let lines = captured(|| {
+      assert_eq!(run("(step (+ 1 (* 2 3)))"), "7");
+    });
+    assert_eq!(lines, vec!["(*,2,3) => 6", "(+,1,(*,2,3)) => (+,1,6)", "(+,1,6) => 7"]);
+  }
+
+  #[test]
+  fn builtin_panics_become_errors() {
+    let res = apply_builtin(|_| panic!("boom"), &[]);
+    assert!(matches!(res, Err(RispErr::Reason(msg)) if msg == "internal error: boom"));
+  }
+
+  #[test]
+  fn sort_from_risp() {
+    assert_eq!(run("(sort (list 3 1 2))"), "(1,2,3)");
+    assert_eq!(run_err("(sort (list 1 \"a\"))"), "cannot sort mixed types");
+  }
+
+  #[test]
+  fn truthy_and_falsy() {
+    assert_eq!(run("(list (truthy? 0) (truthy? nil) (falsy? false) (falsy? 1))"), "(true,false,true,false)");
+    assert_eq!(run("(list (truthy? \"\") (falsy? (list)))"), "(true,true)");
+  }
+
+  #[test]
+  fn doc_looks_up_builtins() {
+    assert_eq!(run("(doc regex-match)"), "\"returns the first match of a regex in a string, or nil\"");
+    assert_eq!(run("(doc no-such-thing)"), "()");
+    assert!(BUILTIN_DOCS.iter().all(|(name, _)| default_env().data.borrow().contains_key(*name)
+      || ["if", "def", "fn"].contains(name)));
+  }
+
+  #[test]
+  fn string_escapes() {
+    assert_eq!(run(r#"(length "a\nb")"#), "3");
+    assert_eq!(run(r#"(length "say \"hi\"")"#), "8");
+    assert_eq!(run(r#"(length "\\")"#), "1");
+    assert_eq!(run(r#"(regex-match "\d+" "ab12")"#), "\"12\"");
+    assert_eq!(run_err(r#"(length "open)"#), "unterminated string");
+  }
+
+  #[test]
+  fn parse_eval_reports_parse_errors() {
+    let env = &mut default_env();
+    assert!(matches!(parse_eval(")".to_string(), env), Some(Err(RispErr::Reason(msg))) if msg == "unexpected `)`"));
+    assert!(matches!(
+      parse_eval("(+ 1".to_string(), env),
+      Some(Err(RispErr::Reason(msg))) if msg == "could not find closing `)`"
+    ));
+    assert!(parse_eval("".to_string(), env).is_none());
+    assert!(parse_eval("; just a comment".to_string(), env).is_none());
+  }
+
+  #[test]
+  fn reductions_keep_each_step() {
+    assert_eq!(run("(reductions + 0 (list 1 2 3))"), "(0,1,3,6)");
+    assert_eq!(run("(reductions + 0 nil)"), "(0)");
+    assert_eq!(run_err("(reductions + 0 5)"), "not a list: 5");
+  }
+
+  #[test]
+  fn parse_eval_evaluates_every_form() {
+    let env = &mut default_env();
+    let res = parse_eval("(def a 1)\n(def b 2) (+ a b)".to_string(), env);
+    assert_eq!(res.unwrap().ok().unwrap().to_string(), "3");
+  }
+
+  #[test]
+  fn open_forms_ignores_strings_and_comments() {
+    assert_eq!(open_forms("(+ 1 2)"), 0);
+    assert_eq!(open_forms("(+ 1 (* 2"), 2);
+    assert_eq!(open_forms("(concat \"(\" ; (\n"), 1);
+    assert_eq!(open_forms(")("), 1);
+  }
+}
+
 /* Local Variables: */
 /* mode: rust */
 /* rust-indent-offset: 2 */
@@ -1,6 +1,15 @@
+use std::cmp::Ordering;
+use std::sync::atomic::{self, AtomicBool};
 use super::RispExp;
 use super::RispErr;
 
+// Rust's Display for f64 is the shortest text that reads back as the
+// same number, so whole numbers show without a decimal point and
+// nothing is rounded away: 0.1 + 0.2 shows as 0.30000000000000004
+pub fn display_number(n: f64) -> String {
+  n.to_string()
+}
+
 pub fn parse_list_of_floats(args: &[RispExp]) -> Result<Vec<f64>, RispErr> {
   args
     .iter()
@@ -8,17 +17,26 @@ pub fn parse_list_of_floats(args: &[RispExp]) -> Result<Vec<f64>, RispErr> {
     .collect()
 }
 
+// The arithmetic functions fold over a lone list argument as if its
+// items had been passed one by one, so (+ (num 5)) is 10
+fn arithmetic_args(args: &[RispExp]) -> Result<Vec<f64>, RispErr> {
+  match args {
+    [RispExp::List(list)] => parse_list_of_floats(list),
+    _ => parse_list_of_floats(args),
+  }
+}
+
 pub fn parse_single_float(exp: &RispExp) -> Result<f64, RispErr> {
   match exp {
     RispExp::Number(num) => Ok(*num),
-    _ => Err(RispErr::Reason("expected a number".to_string())),
+    _ => Err(RispErr::Reason(format!("expected a number: {}", exp))),
   }
 }
 
 pub fn plus_func() -> RispExp {
     RispExp::Func(
 	|args: &[RispExp]| -> Result<RispExp, RispErr> {
-            let sum = parse_list_of_floats(args)?.iter().fold(0.0, |sum, a| sum + a);
+            let sum = arithmetic_args(args)?.iter().fold(0.0, |sum, a| sum + a);
             Ok(RispExp::Number(sum))
 	}
     )
@@ -27,7 +45,7 @@ pub fn plus_func() -> RispExp {
 pub fn mult_func() -> RispExp {
     RispExp::Func(
 	|args: &[RispExp]| -> Result<RispExp, RispErr> {
-            let floats = parse_list_of_floats(args)?;
+            let floats = arithmetic_args(args)?;
 	    let first = *floats.first().ok_or(RispErr::Reason("expected at least one number".to_string()))?;
             let product = floats[1..].iter().fold(first, |sum, a| sum * a);
             Ok(RispExp::Number(product))
@@ -38,7 +56,7 @@ pub fn mult_func() -> RispExp {
 pub fn minus_func() -> RispExp {
     RispExp::Func(
 	|args: &[RispExp]| -> Result<RispExp, RispErr> {
-            let floats = parse_list_of_floats(args)?;
+            let floats = arithmetic_args(args)?;
             let first = *floats.first().ok_or(RispErr::Reason("expected at least one number".to_string()))?;
             let sum_of_rest = floats[1..].iter().fold(0.0, |sum, a| sum + a);
             Ok(RispExp::Number(first - sum_of_rest))
@@ -46,5 +64,444 @@ pub fn minus_func() -> RispExp {
     )
 }
 
-// End
+pub fn div_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+            let floats = arithmetic_args(args)?;
+            let first = *floats.first().ok_or(RispErr::Reason("expected at least one number".to_string()))?;
+            let product_of_rest = floats[1..].iter().fold(1.0, |product, a| product * a);
+            if product_of_rest == 0.0 {
+              return Err(RispErr::Reason("division by zero".to_string()));
+            }
+            Ok(RispExp::Number(first / product_of_rest))
+	}
+    )
+}
+
+fn dividend_and_divisor(args: &[RispExp]) -> Result<(f64, f64), RispErr> {
+  match parse_list_of_floats(args)?[..] {
+    [_, 0.0] => Err(RispErr::Reason("division by zero".to_string())),
+    [dividend, divisor] => Ok((dividend, divisor)),
+    _ => Err(RispErr::Reason("expected two numbers".to_string())),
+  }
+}
+
+// Like Rust's % the result takes the sign of the dividend
+pub fn mod_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+            let (dividend, divisor) = dividend_and_divisor(args)?;
+            Ok(RispExp::Number(dividend % divisor))
+	}
+    )
+}
+
+// Division rounded towards zero
+pub fn quotient_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+            let (dividend, divisor) = dividend_and_divisor(args)?;
+            Ok(RispExp::Number((dividend / divisor).trunc()))
+	}
+    )
+}
+
+// Numbers compare numerically and strings lexicographically, but the
+// two can't be mixed
+fn extreme(args: &[RispExp], keep: Ordering) -> Result<RispExp, RispErr> {
+  match args {
+    // a lone list or range is taken as its items, a range goes one way
+    // so its smallest and largest are at its ends
+    [RispExp::List(list)] => return extreme(list, keep),
+    [RispExp::Range(range)] => {
+      let (first, last) = range.ends().ok_or(RispErr::Reason("expected at least one value".to_string()))?;
+      let best = match keep {
+        Ordering::Greater => first.max(last),
+        _ => first.min(last),
+      };
+      return Ok(RispExp::Number(best));
+    },
+    _ => (),
+  }
+  let first = args.first().ok_or(RispErr::Reason("expected at least one value".to_string()))?;
+  let mut best = first;
+  for arg in &args[1..] {
+    let ordering = match (arg, best) {
+      (RispExp::Number(a), RispExp::Number(b)) => a.partial_cmp(b),
+      (RispExp::Str(a), RispExp::Str(b)) => Some(a.cmp(b)),
+      _ => return Err(RispErr::Reason(format!("cannot compare mixed types: {} and {}", best, arg))),
+    };
+    if ordering == Some(keep) {
+      best = arg;
+    }
+  }
+  match best {
+    RispExp::Number(_) | RispExp::Str(_) => Ok(best.clone()),
+    _ => Err(RispErr::Reason(format!("expected numbers or strings: {}", best))),
+  }
+}
+
+pub fn max_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+            extreme(args, Ordering::Greater)
+	}
+    )
+}
+
+pub fn min_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+            extreme(args, Ordering::Less)
+	}
+    )
+}
+
+fn single_float(args: &[RispExp]) -> Result<f64, RispErr> {
+  match args {
+    [x] => parse_single_float(x),
+    _ => Err(RispErr::Reason("expected one number".to_string())),
+  }
+}
+
+fn single_integer(args: &[RispExp]) -> Result<i64, RispErr> {
+  let x = single_float(args)?;
+  if x.fract() != 0.0 {
+    return Err(RispErr::Reason(format!("expected an integer, got {}", x)));
+  }
+  Ok(x as i64)
+}
+
+pub fn positive_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+            Ok(RispExp::Bool(single_float(args)? > 0.0))
+	}
+    )
+}
+
+pub fn negative_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+            Ok(RispExp::Bool(single_float(args)? < 0.0))
+	}
+    )
+}
+
+pub fn zero_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+            Ok(RispExp::Bool(single_float(args)? == 0.0))
+	}
+    )
+}
+
+pub fn even_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+            Ok(RispExp::Bool(single_integer(args)? % 2 == 0))
+	}
+    )
+}
+
+pub fn odd_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+            Ok(RispExp::Bool(single_integer(args)? % 2 != 0))
+	}
+    )
+}
+
+// value, min and max, where min may not be more than max
+fn bounds(args: &[RispExp]) -> Result<(f64, f64, f64), RispErr> {
+  match parse_list_of_floats(args)?[..] {
+    [_, min, max] if min > max => Err(RispErr::Reason(format!("min {} is more than max {}", min, max))),
+    [x, min, max] => Ok((x, min, max)),
+    _ => Err(RispErr::Reason("expected a value, a min and a max".to_string())),
+  }
+}
+
+pub fn clamp_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+            let (x, min, max) = bounds(args)?;
+            Ok(RispExp::Number(x.clamp(min, max)))
+	}
+    )
+}
+
+pub fn in_range_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+            let (x, min, max) = bounds(args)?;
+            Ok(RispExp::Bool(min <= x && x <= max))
+	}
+    )
+}
+
+// The tolerance approx= uses when it isn't given one, and = uses when
+// risp is started with --approx
+pub const EPSILON: f64 = 1e-9;
+
+pub static APPROX_EQUAL: AtomicBool = AtomicBool::new(false);
+
+pub fn numbers_equal(a: f64, b: f64) -> bool {
+  numbers_equal_with(a, b, APPROX_EQUAL.load(atomic::Ordering::Relaxed))
+}
+
+fn numbers_equal_with(a: f64, b: f64, approx: bool) -> bool {
+  if approx {
+    (a - b).abs() <= EPSILON
+  } else {
+    a == b
+  }
+}
+
+pub fn approx_equal_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+            let (a, b, tolerance) = match parse_list_of_floats(args)?[..] {
+              [a, b] => (a, b, EPSILON),
+              [_, _, tolerance] if tolerance < 0.0 => {
+                return Err(RispErr::Reason(format!("tolerance must not be negative: {}", tolerance)));
+              },
+              [a, b, tolerance] => (a, b, tolerance),
+              _ => return Err(RispErr::Reason("expected two numbers and an optional tolerance".to_string())),
+            };
+            Ok(RispExp::Bool((a - b).abs() <= tolerance))
+	}
+    )
+}
+
+// The stats functions take a single non-empty list of numbers
+fn list_of_floats(args: &[RispExp]) -> Result<Vec<f64>, RispErr> {
+  let floats = match args {
+    [RispExp::List(list)] => parse_list_of_floats(list)?,
+    [x] => return Err(RispErr::Reason(format!("expected a list of numbers: {}", x))),
+    _ => return Err(RispErr::Reason("expected a list of numbers".to_string())),
+  };
+  if floats.is_empty() {
+    return Err(RispErr::Reason("expected at least one number".to_string()));
+  }
+  Ok(floats)
+}
+
+fn mean(floats: &[f64]) -> f64 {
+  floats.iter().sum::<f64>() / floats.len() as f64
+}
+
+// Zero for an empty list or range, like + with no arguments. Folded
+// from 0 as an f64 sum starts at -0
+pub fn sum_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+            match args {
+              [RispExp::Range(range)] => Ok(RispExp::Number(range.iter().fold(0.0, |a, b| a + b))),
+              [RispExp::List(list)] => Ok(RispExp::Number(parse_list_of_floats(list)?.into_iter().fold(0.0, |a, b| a + b))),
+              [x] => Err(RispErr::Reason(format!("expected a list of numbers or a range: {}", x))),
+              _ => Err(RispErr::Reason("expected a list of numbers or a range".to_string())),
+            }
+	}
+    )
+}
+
+pub fn mean_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+            Ok(RispExp::Number(mean(&list_of_floats(args)?)))
+	}
+    )
+}
+
+pub fn median_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+            let mut floats = list_of_floats(args)?;
+            floats.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+            let mid = floats.len() / 2;
+            if floats.len() % 2 == 0 {
+              Ok(RispExp::Number((floats[mid - 1] + floats[mid]) / 2.0))
+            } else {
+              Ok(RispExp::Number(floats[mid]))
+            }
+	}
+    )
+}
 
+// The most common number, a tie goes to the one that came first
+pub fn mode_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+            let floats = list_of_floats(args)?;
+            let mut counts: Vec<(f64, usize)> = Vec::new();
+            for x in floats {
+              match counts.iter_mut().find(|(y, _)| *y == x) {
+                Some((_, n)) => *n += 1,
+                None => counts.push((x, 1)),
+              }
+            }
+            let mut best = counts[0];
+            for c in &counts[1..] {
+              if c.1 > best.1 {
+                best = *c;
+              }
+            }
+            Ok(RispExp::Number(best.0))
+	}
+    )
+}
+
+// Population standard deviation, the list is all there is
+pub fn stddev_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+            let floats = list_of_floats(args)?;
+            let m = mean(&floats);
+            let variance = floats.iter().map(|x| (x - m) * (x - m)).sum::<f64>() / floats.len() as f64;
+            Ok(RispExp::Number(variance.sqrt()))
+	}
+    )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_helpers::*;
+
+  #[test]
+  fn max_and_min() {
+    assert_eq!(call(max_func(), &numbers(&[3.0, 9.0, 1.0])), Ok("9".to_string()));
+    assert_eq!(call(min_func(), &numbers(&[3.0, 9.0, 1.0])), Ok("1".to_string()));
+    let words = [s("pear"), s("apple")];
+    assert_eq!(call(max_func(), &words), Ok("\"pear\"".to_string()));
+    assert_eq!(call(min_func(), &words), Ok("\"apple\"".to_string()));
+    assert_eq!(
+      call(max_func(), &[n(1.0), s("a")]),
+      Err("cannot compare mixed types: 1 and \"a\"".to_string())
+    );
+    assert_eq!(call(max_func(), &[]), Err("expected at least one value".to_string()));
+  }
+
+  #[test]
+  fn display_reads_back_as_the_same_number() {
+    assert_eq!(display_number(0.1 + 0.2), "0.30000000000000004");
+    assert_eq!(display_number(3.0), "3");
+    assert_eq!(display_number(-2.5), "-2.5");
+    assert_eq!(display_number(-0.0), "-0");
+    assert_eq!(display_number(f64::INFINITY), "inf");
+    for x in [0.1 + 0.2, 12345678901234567.0, 1.0 / 3.0, 123456.78901234567, 5e-324] {
+      assert_eq!(display_number(x).parse::<f64>(), Ok(x));
+    }
+  }
+
+  #[test]
+  fn sign_predicates() {
+    assert_eq!(call(positive_func(), &numbers(&[2.0])), Ok("true".to_string()));
+    assert_eq!(call(positive_func(), &numbers(&[0.0])), Ok("false".to_string()));
+    assert_eq!(call(negative_func(), &numbers(&[-2.0])), Ok("true".to_string()));
+    assert_eq!(call(zero_func(), &numbers(&[0.0])), Ok("true".to_string()));
+    assert_eq!(call(zero_func(), &numbers(&[1.0, 2.0])), Err("expected one number".to_string()));
+  }
+
+  #[test]
+  fn even_and_odd_need_integers() {
+    assert_eq!(call(even_func(), &numbers(&[4.0])), Ok("true".to_string()));
+    assert_eq!(call(odd_func(), &numbers(&[-3.0])), Ok("true".to_string()));
+    assert_eq!(call(odd_func(), &numbers(&[4.0])), Ok("false".to_string()));
+    assert_eq!(call(even_func(), &numbers(&[2.5])), Err("expected an integer, got 2.5".to_string()));
+  }
+
+  #[test]
+  fn clamp_and_in_range() {
+    assert_eq!(call(clamp_func(), &numbers(&[15.0, 0.0, 10.0])), Ok("10".to_string()));
+    assert_eq!(call(clamp_func(), &numbers(&[-5.0, 0.0, 10.0])), Ok("0".to_string()));
+    assert_eq!(call(clamp_func(), &numbers(&[5.0, 0.0, 10.0])), Ok("5".to_string()));
+    assert_eq!(call(in_range_func(), &numbers(&[10.0, 0.0, 10.0])), Ok("true".to_string()));
+    assert_eq!(call(in_range_func(), &numbers(&[11.0, 0.0, 10.0])), Ok("false".to_string()));
+    assert_eq!(call(clamp_func(), &numbers(&[1.0, 10.0, 0.0])), Err("min 10 is more than max 0".to_string()));
+  }
+
+  #[test]
+  fn stats() {
+    assert_eq!(call(mean_func(), &[nums(&[1.0, 2.0, 3.0, 4.0])]), Ok("2.5".to_string()));
+    assert_eq!(call(median_func(), &[nums(&[5.0, 1.0, 3.0])]), Ok("3".to_string()));
+    assert_eq!(call(median_func(), &[nums(&[4.0, 1.0, 3.0, 2.0])]), Ok("2.5".to_string()));
+    assert_eq!(call(mode_func(), &[nums(&[1.0, 2.0, 2.0, 3.0])]), Ok("2".to_string()));
+    assert_eq!(call(mode_func(), &[nums(&[3.0, 1.0])]), Ok("3".to_string()));
+    assert_eq!(call(stddev_func(), &[nums(&[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0])]), Ok("2".to_string()));
+  }
+
+  #[test]
+  fn stats_need_a_non_empty_list() {
+    assert_eq!(call(mean_func(), &[nums(&[])]), Err("expected at least one number".to_string()));
+    assert_eq!(call(median_func(), &numbers(&[1.0])), Err("expected a list of numbers: 1".to_string()));
+    assert_eq!(call(stddev_func(), &[]), Err("expected a list of numbers".to_string()));
+  }
+
+  #[test]
+  fn arithmetic() {
+    assert_eq!(call(plus_func(), &numbers(&[1.0, 2.0, 3.0])), Ok("6".to_string()));
+    assert_eq!(call(plus_func(), &[]), Ok("0".to_string()));
+    assert_eq!(call(minus_func(), &numbers(&[10.0, 3.0, 2.0])), Ok("5".to_string()));
+    assert_eq!(call(mult_func(), &numbers(&[2.0, 3.0, 4.0])), Ok("24".to_string()));
+    assert_eq!(call(div_func(), &numbers(&[12.0, 3.0, 2.0])), Ok("2".to_string()));
+    assert_eq!(call(mult_func(), &[]), Err("expected at least one number".to_string()));
+    assert_eq!(
+      call(plus_func(), &[n(1.0), s("a")]),
+      Err("expected a number: \"a\"".to_string())
+    );
+  }
+
+  #[test]
+  fn division_by_zero_is_an_error() {
+    assert_eq!(call(div_func(), &numbers(&[1.0, 0.0])), Err("division by zero".to_string()));
+    assert_eq!(call(div_func(), &numbers(&[0.0, 1.0])), Ok("0".to_string()));
+  }
+
+  #[test]
+  fn mod_and_quotient_follow_the_dividend() {
+    assert_eq!(call(mod_func(), &numbers(&[7.0, 3.0])), Ok("1".to_string()));
+    assert_eq!(call(mod_func(), &numbers(&[-7.0, 3.0])), Ok("-1".to_string()));
+    assert_eq!(call(quotient_func(), &numbers(&[7.0, 2.0])), Ok("3".to_string()));
+    assert_eq!(call(quotient_func(), &numbers(&[-7.0, 2.0])), Ok("-3".to_string()));
+    assert_eq!(call(mod_func(), &numbers(&[7.0])), Err("expected two numbers".to_string()));
+    assert_eq!(call(mod_func(), &numbers(&[1.0, 0.0])), Err("division by zero".to_string()));
+    assert_eq!(call(quotient_func(), &numbers(&[1.0, 0.0])), Err("division by zero".to_string()));
+  }
+
+  #[test]
+  fn approx_equal_tolerance() {
+    assert_eq!(call(approx_equal_func(), &numbers(&[0.1 + 0.2, 0.3])), Ok("true".to_string()));
+    assert_eq!(call(approx_equal_func(), &numbers(&[1.0, 1.1])), Ok("false".to_string()));
+    assert_eq!(call(approx_equal_func(), &numbers(&[1.0, 1.1, 0.2])), Ok("true".to_string()));
+    assert_eq!(
+      call(approx_equal_func(), &numbers(&[1.0, 1.1, -0.2])),
+      Err("tolerance must not be negative: -0.2".to_string())
+    );
+  }
+
+  #[test]
+  fn numbers_equal_with_tolerance() {
+    assert!(!numbers_equal_with(0.1 + 0.2, 0.3, false));
+    assert!(numbers_equal_with(0.1 + 0.2, 0.3, true));
+    assert!(numbers_equal_with(1.0, 1.0 + EPSILON / 2.0, true));
+    assert!(!numbers_equal_with(1.0, 1.0 + EPSILON * 2.0, true));
+    assert!(numbers_equal_with(2.0, 2.0, false));
+  }
+
+  #[test]
+  fn sum_lists() {
+    assert_eq!(call(sum_func(), &[nums(&[1.0, 2.5, 3.0])]), Ok("6.5".to_string()));
+    assert_eq!(call(sum_func(), &[nums(&[])]), Ok("0".to_string()));
+    assert_eq!(call(sum_func(), &numbers(&[1.0, 2.0])), Err("expected a list of numbers or a range".to_string()));
+    assert_eq!(call(sum_func(), &[l(vec![s("a")])]), Err("expected a number: \"a\"".to_string()));
+  }
+
+  #[test]
+  fn arithmetic_folds_over_a_lone_list() {
+    assert_eq!(call(plus_func(), &[nums(&[0.0, 1.0, 2.0, 3.0, 4.0])]), Ok("10".to_string()));
+    assert_eq!(call(mult_func(), &[nums(&[2.0, 5.0])]), Ok("10".to_string()));
+  }
+}
+
+// End
@@ -0,0 +1,124 @@
+use regex::Regex;
+use super::RispExp;
+use super::RispErr;
+
+fn string_args(args: &[RispExp], count: usize) -> Result<Vec<&String>, RispErr> {
+    if args.len() != count {
+	return Err(RispErr::Reason(format!("pass {} strings", count)));
+    }
+
+    args.iter().map(|arg| match arg {
+	RispExp::Str(s) => Ok(s),
+	_ => Err(RispErr::Reason("args must be strings".to_string()))
+    }).collect()
+}
+
+// Patterns are compiled on each call, the compile error is the risp error
+fn compile(pattern: &str) -> Result<Regex, RispErr> {
+    Regex::new(pattern).map_err(|e| RispErr::Reason(e.to_string()))
+}
+
+pub fn match_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    let args = string_args(args, 2)?;
+	    let re = compile(args[0])?;
+	    match re.find(args[1]) {
+		Some(m) => Ok(RispExp::Str(m.as_str().to_string())),
+		None => Ok(RispExp::List(vec![]))
+	    }
+	}
+    )
+}
+
+pub fn find_all_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    let args = string_args(args, 2)?;
+	    let re = compile(args[0])?;
+	    let matches = re.find_iter(args[1])
+		.map(|m| RispExp::Str(m.as_str().to_string()))
+		.collect();
+	    Ok(RispExp::List(matches))
+	}
+    )
+}
+
+pub fn replace_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    let args = string_args(args, 3)?;
+	    let re = compile(args[0])?;
+	    Ok(RispExp::Str(re.replace_all(args[2], args[1].as_str()).to_string()))
+	}
+    )
+}
+
+// Named groups come back as an assoc list of (name text), a group
+// that took no part in the match has nil as its text
+pub fn captures_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    let args = string_args(args, 2)?;
+	    let re = compile(args[0])?;
+	    let caps = match re.captures(args[1]) {
+		Some(caps) => caps,
+		None => return Ok(RispExp::List(vec![]))
+	    };
+	    let pairs = re.capture_names()
+		.flatten()
+		.map(|name| {
+		    let text = match caps.name(name) {
+			Some(m) => RispExp::Str(m.as_str().to_string()),
+			None => RispExp::List(vec![])
+		    };
+		    RispExp::List(vec![RispExp::Str(name.to_string()), text])
+		})
+		.collect();
+	    Ok(RispExp::List(pairs))
+	}
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+
+    #[test]
+    fn match_returns_the_first_match_or_nil() {
+	assert_eq!(call(match_func(), &[s("[0-9]+"), s("ab 12 34")]), Ok("\"12\"".to_string()));
+	assert_eq!(call(match_func(), &[s("[0-9]+"), s("abc")]), Ok("()".to_string()));
+    }
+
+    #[test]
+    fn find_all_matches() {
+	assert_eq!(call(find_all_func(), &[s("[0-9]+"), s("a1 b22 c333")]), Ok("(\"1\",\"22\",\"333\")".to_string()));
+	assert_eq!(call(find_all_func(), &[s("x"), s("abc")]), Ok("()".to_string()));
+    }
+
+    #[test]
+    fn replace_all_matches() {
+	assert_eq!(call(replace_func(), &[s("o"), s("0"), s("foo boo")]), Ok("\"f00 b00\"".to_string()));
+	assert_eq!(call(replace_func(), &[s("(\\w+)@"), s("$1 at "), s("me@home")]), Ok("\"me at home\"".to_string()));
+    }
+
+    #[test]
+    fn bad_args_and_patterns() {
+	assert_eq!(call(match_func(), &[s("a")]), Err("pass 2 strings".to_string()));
+	assert_eq!(call(match_func(), &[s("a"), RispExp::Number(1.0)]), Err("args must be strings".to_string()));
+	assert!(call(match_func(), &[s("("), s("a")]).unwrap_err().contains("regex parse error"));
+    }
+
+    #[test]
+    fn captures_named_groups() {
+	let re = s("(?P<user>\\w+)@(?P<host>\\w+)(?P<port>:\\d+)?");
+	assert_eq!(
+	    call(captures_func(), &[re.clone(), s("me@home")]),
+	    Ok("((\"user\",\"me\"),(\"host\",\"home\"),(\"port\",()))".to_string())
+	);
+	assert_eq!(call(captures_func(), &[re, s("nothing")]), Ok("()".to_string()));
+    }
+}
+
+// End
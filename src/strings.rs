@@ -0,0 +1,319 @@
+use super::RispExp;
+use super::RispErr;
+use super::RispValueString;
+
+pub fn string_eq_ignore_case() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    let (a, b) = two_strings(args)?;
+	    Ok(RispExp::Bool(a.eq_ignore_ascii_case(b)))
+	}
+    )
+}
+
+fn two_strings(args: &[RispExp]) -> Result<(&String, &String), RispErr> {
+    if args.len() != 2 {
+	return Err(RispErr::Reason("pass two strings".to_string()));
+    }
+
+    match (&args[0], &args[1]) {
+	(RispExp::Str(a), RispExp::Str(b)) => Ok((a, b)),
+	_ => Err(RispErr::Reason("args must be strings".to_string()))
+    }
+}
+
+// Positions are in chars, not bytes, and a miss is nil
+fn char_position(s: &str, byte_index: Option<usize>) -> RispExp {
+    match byte_index {
+	Some(i) => RispExp::Number(s[..i].chars().count() as f64),
+	None => RispExp::List(vec![])
+    }
+}
+
+pub fn index_of() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    let (s, sub) = two_strings(args)?;
+	    Ok(char_position(s, s.find(sub.as_str())))
+	}
+    )
+}
+
+pub fn last_index_of() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    let (s, sub) = two_strings(args)?;
+	    Ok(char_position(s, s.rfind(sub.as_str())))
+	}
+    )
+}
+
+// The byte offset of a char position, past the end clamps to the end
+fn byte_offset(s: &str, chars: usize) -> usize {
+    s.char_indices().nth(chars).map(|(i, _)| i).unwrap_or(s.len())
+}
+
+// Indexes are chars and clamp to the string rather than failing
+pub fn substring_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    let (s, bounds) = match args {
+		[RispExp::Str(s), bounds @ ..] if bounds.len() == 1 || bounds.len() == 2 => (s, bounds),
+		_ => return Err(RispErr::Reason("pass a string, a start and an optional end".to_string()))
+	    };
+	    let mut indexes = Vec::new();
+	    for bound in bounds {
+		match bound {
+		    RispExp::Number(n) if n.fract() == 0.0 => indexes.push(n.max(0.0) as usize),
+		    _ => return Err(RispErr::Reason(format!("index must be an integer: {}", bound)))
+		}
+	    }
+	    let start = indexes[0];
+	    let end = indexes.get(1).copied().unwrap_or(usize::MAX);
+	    if start > end {
+		return Err(RispErr::Reason(format!("start {} is after end {}", start, end)));
+	    }
+	    Ok(RispExp::Str(s[byte_offset(s, start)..byte_offset(s, end)].to_string()))
+	}
+    )
+}
+
+pub fn char_code() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    let s = match args.first() {
+		Some(RispExp::Str(s)) => s,
+		_ => return Err(RispErr::Reason("pass a one character string".to_string()))
+	    };
+	    let mut chars = s.chars();
+	    match (chars.next(), chars.next()) {
+		(Some(c), None) => Ok(RispExp::Number(c as u32 as f64)),
+		_ => Err(RispErr::Reason("string must be a single character".to_string()))
+	    }
+	}
+    )
+}
+
+pub fn code_char() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    let code = match args.first() {
+		Some(RispExp::Number(n)) if n.fract() == 0.0 && *n >= 0.0 && *n <= u32::MAX as f64 => *n as u32,
+		Some(RispExp::Number(n)) => return Err(RispErr::Reason(format!("invalid code point {}", n))),
+		_ => return Err(RispErr::Reason("pass a code point".to_string()))
+	    };
+	    match char::from_u32(code) {
+		Some(c) => Ok(RispExp::Str(c.to_string())),
+		None => Err(RispErr::Reason(format!("invalid code point {}", code)))
+	    }
+	}
+    )
+}
+
+// Lists join into a list, anything else joins into a string with
+// numbers and bools coerced to their text. The first argument decides
+// which and lists can't be mixed with the rest
+pub fn concat_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    match args.first() {
+		Some(RispExp::List(_)) => {
+		    let mut res = Vec::new();
+		    for arg in args {
+			match arg {
+			    RispExp::List(l) => res.extend(l.iter().cloned()),
+			    _ => return Err(RispErr::Reason(format!("cannot concat a list with a non list: {}", arg)))
+			}
+		    }
+		    Ok(RispExp::List(res))
+		},
+		_ => {
+		    let mut res = String::new();
+		    for arg in args {
+			match arg {
+			    RispExp::List(_) => return Err(RispErr::Reason(format!("cannot concat a string with a list: {}", arg))),
+			    _ => res.push_str(&arg.lisp_val())
+			}
+		    }
+		    Ok(RispExp::Str(res))
+		}
+	    }
+	}
+    )
+}
+
+fn one_string(args: &[RispExp]) -> Result<&String, RispErr> {
+    match args {
+	[RispExp::Str(s)] => Ok(s),
+	[x] => Err(RispErr::Reason(format!("arg is not a string: {}", x))),
+	_ => Err(RispErr::Reason("pass a string".to_string()))
+    }
+}
+
+fn strings_list<'a>(strs: impl Iterator<Item = &'a str>) -> RispExp {
+    RispExp::List(strs.map(|s| RispExp::Str(s.to_string())).collect())
+}
+
+// \n or \r\n line endings, a final newline doesn't make an empty line
+pub fn lines_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    Ok(strings_list(one_string(args)?.lines()))
+	}
+    )
+}
+
+pub fn words_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    Ok(strings_list(one_string(args)?.split_whitespace()))
+	}
+    )
+}
+
+// Unicode aware, so a character can change length, ß upcases to SS
+pub fn upcase_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    Ok(RispExp::Str(one_string(args)?.to_uppercase()))
+	}
+    )
+}
+
+pub fn downcase_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    Ok(RispExp::Str(one_string(args)?.to_lowercase()))
+	}
+    )
+}
+
+pub fn trim_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    Ok(RispExp::Str(one_string(args)?.trim().to_string()))
+	}
+    )
+}
+
+// An empty separator splits into characters
+pub fn split_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    let (s, sep) = two_strings(args)?;
+	    if sep.is_empty() {
+		return Ok(RispExp::List(s.chars().map(|c| RispExp::Str(c.to_string())).collect()));
+	    }
+	    Ok(strings_list(s.split(sep.as_str())))
+	}
+    )
+}
+
+// Items that aren't strings are joined as their text
+pub fn join_func() -> RispExp {
+    RispExp::Func(
+	|args: &[RispExp]| -> Result<RispExp, RispErr> {
+	    match args {
+		[RispExp::List(l), RispExp::Str(sep)] => {
+		    let strs: Vec<String> = l.iter().map(|x| x.lisp_val()).collect();
+		    Ok(RispExp::Str(strs.join(sep)))
+		},
+		_ => Err(RispErr::Reason("pass a list and a separator string".to_string()))
+	    }
+	}
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+
+    fn q(x: &str) -> Result<String, String> {
+	Ok(format!("\"{}\"", x))
+    }
+
+    #[test]
+    fn string_eq_ignores_case() {
+	assert_eq!(call(string_eq_ignore_case(), &[s("Hello"), s("hELLO")]), Ok("true".to_string()));
+	assert_eq!(call(string_eq_ignore_case(), &[s("Hello"), s("Help")]), Ok("false".to_string()));
+	assert_eq!(call(string_eq_ignore_case(), &[s("a")]), Err("pass two strings".to_string()));
+	assert_eq!(call(string_eq_ignore_case(), &[s("a"), n(1.0)]), Err("args must be strings".to_string()));
+    }
+
+    #[test]
+    fn index_of_counts_chars() {
+	assert_eq!(call(index_of(), &[s("banana"), s("an")]), Ok("1".to_string()));
+	assert_eq!(call(last_index_of(), &[s("banana"), s("an")]), Ok("3".to_string()));
+	assert_eq!(call(index_of(), &[s("héllo"), s("l")]), Ok("2".to_string()));
+	assert_eq!(call(index_of(), &[s("banana"), s("x")]), Ok("()".to_string()));
+    }
+
+    #[test]
+    fn index_of_finds_overlapping_matches() {
+	assert_eq!(call(index_of(), &[s("aaa"), s("aa")]), Ok("0".to_string()));
+	assert_eq!(call(last_index_of(), &[s("aaa"), s("aa")]), Ok("1".to_string()));
+    }
+
+    #[test]
+    fn char_code_round_trips() {
+	assert_eq!(call(char_code(), &[s("A")]), Ok("65".to_string()));
+	assert_eq!(call(code_char(), &[n(955.0)]), q("λ"));
+	assert_eq!(call(char_code(), &[s("ab")]), Err("string must be a single character".to_string()));
+	assert_eq!(call(code_char(), &[n(55296.0)]), Err("invalid code point 55296".to_string()));
+	assert_eq!(call(code_char(), &[n(-1.0)]), Err("invalid code point -1".to_string()));
+    }
+
+    #[test]
+    fn concat_strings_and_lists() {
+	assert_eq!(call(concat_func(), &[s("a"), s("b"), s("c")]), q("abc"));
+	assert_eq!(call(concat_func(), &[]), q(""));
+	let list = nums(&[1.0, 2.0]);
+	assert_eq!(call(concat_func(), &[list.clone(), nums(&[3.0])]), Ok("(1,2,3)".to_string()));
+	assert_eq!(call(concat_func(), &[list.clone(), s("a")]), Err("cannot concat a list with a non list: \"a\"".to_string()));
+	assert_eq!(call(concat_func(), &[s("a"), list]), Err("cannot concat a string with a list: (1,2)".to_string()));
+    }
+
+    #[test]
+    fn lines_and_words() {
+	assert_eq!(call(lines_func(), &[s("a\nb\r\nc\n")]), Ok("(\"a\",\"b\",\"c\")".to_string()));
+	assert_eq!(call(words_func(), &[s("  one two\tthree ")]), Ok("(\"one\",\"two\",\"three\")".to_string()));
+	assert_eq!(call(lines_func(), &[n(1.0)]), Err("arg is not a string: 1".to_string()));
+    }
+
+    #[test]
+    fn concat_turns_values_into_text() {
+	assert_eq!(call(concat_func(), &[s("n="), n(1.0), s(" "), RispExp::Bool(true)]), q("n=1 true"));
+	assert_eq!(call(concat_func(), &[n(1.5), n(2.0)]), q("1.52"));
+    }
+
+    #[test]
+    fn substring_clamps() {
+	assert_eq!(call(substring_func(), &[s("hello"), n(1.0), n(3.0)]), q("el"));
+	assert_eq!(call(substring_func(), &[s("hello"), n(2.0)]), q("llo"));
+	assert_eq!(call(substring_func(), &[s("hello"), n(-2.0), n(2.0)]), q("he"));
+	assert_eq!(call(substring_func(), &[s("hello"), n(3.0), n(99.0)]), q("lo"));
+	assert_eq!(call(substring_func(), &[s("héllo"), n(1.0), n(2.0)]), q("é"));
+	assert_eq!(call(substring_func(), &[s("hello"), n(3.0), n(1.0)]), Err("start 3 is after end 1".to_string()));
+	assert_eq!(call(substring_func(), &[s("hello"), n(1.5)]), Err("index must be an integer: 1.5".to_string()));
+    }
+
+    #[test]
+    fn split_and_join() {
+	assert_eq!(call(split_func(), &[s("a,b,,c"), s(",")]), Ok("(\"a\",\"b\",\"\",\"c\")".to_string()));
+	assert_eq!(call(split_func(), &[s("héj"), s("")]), Ok("(\"h\",\"é\",\"j\")".to_string()));
+	let items = RispExp::List(vec![s("a"), n(1.0), RispExp::Bool(false)]);
+	assert_eq!(call(join_func(), &[items, s("-")]), q("a-1-false"));
+	assert_eq!(call(join_func(), &[s("a"), s("-")]), Err("pass a list and a separator string".to_string()));
+    }
+
+    #[test]
+    fn case_and_trim() {
+	assert_eq!(call(upcase_func(), &[s("straße")]), q("STRASSE"));
+	assert_eq!(call(downcase_func(), &[s("HeLLo")]), q("hello"));
+	assert_eq!(call(trim_func(), &[s("  hi \n")]), q("hi"));
+	assert_eq!(call(upcase_func(), &[]), Err("pass a string".to_string()));
+    }
+}
+
+// End
@@ -0,0 +1,38 @@
+// Shorthand the unit tests share for building values and calling
+// builtins, errors come back as their message
+use super::RispExp;
+use super::RispErr;
+
+pub fn call_exp(f: RispExp, args: &[RispExp]) -> Result<RispExp, String> {
+    match f {
+	RispExp::Func(f) => f(args).map_err(|RispErr::Reason(msg)| msg),
+	_ => panic!("not a builtin: {}", f)
+    }
+}
+
+// The result as it would print
+pub fn call(f: RispExp, args: &[RispExp]) -> Result<String, String> {
+    call_exp(f, args).map(|exp| exp.to_string())
+}
+
+pub fn n(x: f64) -> RispExp {
+    RispExp::Number(x)
+}
+
+pub fn s(x: &str) -> RispExp {
+    RispExp::Str(x.to_string())
+}
+
+pub fn l(xs: Vec<RispExp>) -> RispExp {
+    RispExp::List(xs)
+}
+
+pub fn numbers(xs: &[f64]) -> Vec<RispExp> {
+    xs.iter().map(|x| n(*x)).collect()
+}
+
+pub fn nums(xs: &[f64]) -> RispExp {
+    l(numbers(xs))
+}
+
+// End
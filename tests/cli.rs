@@ -0,0 +1,72 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn risp(args: &[&str], stdin: &str) -> Output {
+  let mut child = Command::new(env!("CARGO_BIN_EXE_risp"))
+    .args(args)
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()
+    .expect("risp should start");
+  child.stdin.take().unwrap().write_all(stdin.as_bytes()).unwrap();
+  child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+  String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+fn stderr(output: &Output) -> String {
+  String::from_utf8_lossy(&output.stderr).to_string()
+}
+
+#[test]
+fn eval_flag_prints_the_result() {
+  let output = risp(&["-e", "(+ 1 2)"], "");
+  assert!(output.status.success());
+  assert_eq!(stdout(&output), "3\n");
+}
+
+#[test]
+fn each_eval_flag_shares_the_env() {
+  let output = risp(&["-e", "(def x 2)", "--eval", "(* x 3)"], "");
+  assert!(output.status.success());
+  assert_eq!(stdout(&output), "x\n6\n");
+}
+
+#[test]
+fn errors_exit_non_zero() {
+  let output = risp(&["-e", "(car 5)", "-e", "(+ 1 2)"], "");
+  assert_eq!(output.status.code(), Some(1));
+  assert_eq!(stdout(&output), "");
+  assert_eq!(stderr(&output), "arg is not a list: 5\n");
+}
+
+#[test]
+fn bad_arguments_exit_two() {
+  let output = risp(&["--what"], "");
+  assert_eq!(output.status.code(), Some(2));
+  assert_eq!(stderr(&output), "unknown argument --what\n");
+}
+
+#[test]
+fn piped_program_prints_the_last_value() {
+  let output = risp(&[], "(def a 1)\n(def b\n  2)\n(+ a b)\n");
+  assert!(output.status.success());
+  assert_eq!(stdout(&output), "3\n");
+}
+
+#[test]
+fn piped_parse_errors_exit_non_zero() {
+  let output = risp(&[], "(+ 1\n");
+  assert_eq!(output.status.code(), Some(1));
+  assert_eq!(stdout(&output), "");
+}
+
+#[test]
+fn json_output() {
+  let output = risp(&["-e", "(list 1 2 3)", "--json"], "");
+  assert!(output.status.success());
+  assert_eq!(stdout(&output), "[1,2,3]\n");
+}